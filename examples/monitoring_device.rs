@@ -11,10 +11,10 @@ fn main() {
         match read.recv() {
             Ok(v) => {
                 match v {
-                    CentralEvent::DeviceAdd(id) => {
+                    CentralEvent::DeviceAdd { id, .. } => {
                         println!("Add:{:?}",id);
                     },
-                    CentralEvent::DeviceRemove(device) => {
+                    CentralEvent::DeviceRemove { device, .. } => {
                         println!("Remove:{:?}",device.id);
                     },
                 }
@@ -1,96 +1,866 @@
-use std::{sync::{ Arc, Mutex}, thread::{JoinHandle,spawn}};
-use std::fmt::{self, Debug, Formatter};
+use std::{ffi::OsStr, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}, thread::{JoinHandle,sleep,spawn}, time::{Duration, Instant, SystemTime}};
+use std::{collections::VecDeque, fmt::{self, Debug, Formatter}, ops::Deref};
 use anyhow::{Result, Ok};
-use crossbeam_channel::Receiver;
+use crossbeam_channel::{Receiver, RecvError, RecvTimeoutError};
 use uuid::Uuid;
 
 use super::{
     Error,
     CentralEvent,
-    manager::Manager,
-    hid_device::{HidDevice,all_hid_device},
+    manager::{Manager, DeviceChange, Subscription},
+    filter::DeviceFilter,
+    hid_device::{HidDevice,IdScheme,OpenOptions,all_hid_device_with_options,diff_devices,present_device_paths},
     pnp_detect::PnPDetectWindows,
 };
 
+/// 枚举时打开单个设备的超时时间，超时的设备被跳过，避免一个无响应的设备阻塞 start 和热插拔处理
+const ENUM_OPEN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// stop 等待热插拔监听线程退出的最长时间
+const STOP_TIMEOUT: Duration = Duration::from_secs(1);
+
 #[derive(Clone)]
 pub struct Adapter {
     manager: Arc<Manager>,
+    /// 保存到 manager 中的设备
+    tracked: DeviceFilter,
+    /// 产生事件的设备，是 tracked 的子集时才有意义
+    filter: DeviceFilter,
+    /// 枚举时生成设备 id 的方式
+    id_scheme: IdScheme,
     thread_handle: Arc<Mutex<Option<JoinHandle<()>>>>,
+    stop_flag: Arc<Mutex<Option<Arc<AtomicBool>>>>,
 }
 
 impl Debug for Adapter {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         f.debug_struct("Adapter")
             .field("manager", &self.manager)
+            .field("tracked", &self.tracked)
+            .field("filter", &self.filter)
+            .field("id_scheme", &self.id_scheme)
             .finish()
     }
 }
 
+/// 最后一个 Adapter 克隆销毁时停止热插拔监听线程
+impl Drop for Adapter {
+    fn drop(&mut self) {
+        if Arc::strong_count(&self.thread_handle) == 1 {
+            self.stop();
+        }
+    }
+}
+
+
+/// Adapter::subscribe 返回的事件接收端，通过 Deref 当作 crossbeam Receiver 使用
+///
+/// 用于统计存活的接收端，全部 drop 后 Adapter 进入空闲状态，见 Adapter::subscribe
+#[derive(Debug, Clone)]
+pub struct EventReceiver {
+    receiver: Receiver<CentralEvent>,
+    _subscription: Subscription,
+}
+
+impl Deref for EventReceiver {
+    type Target = Receiver<CentralEvent>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.receiver
+    }
+}
+
+/// CoalescedEvents 收到的事件
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CoalescedEvent {
+    /// 没有被合并的事件
+    Event(CentralEvent),
+    /// 设备被快速拔插，移除和重新连接合并为一个事件，id 为重新连接后的设备 id，time 为重新连接的时间
+    DeviceUpdate { id: Uuid, time: SystemTime },
+}
+
+/// Adapter::coalesce_events 返回的事件接收端，快速拔插产生的移除、连接事件合并为 CoalescedEvent::DeviceUpdate
+#[derive(Debug)]
+pub struct CoalescedEvents {
+    events: EventReceiver,
+    manager: Arc<Manager>,
+    window: Duration,
+    ready: Mutex<VecDeque<CoalescedEvent>>,
+}
+
+impl CoalescedEvents {
+    /// 阻塞接收下一个事件
+    ///
+    /// 收到 DeviceRemove 时继续接收 window 时间内的事件，移除之后同一 container id 的设备重新连接时，
+    /// 两个事件合并为一个 DeviceUpdate，因此 DeviceRemove 最多会延迟 window 才收到
+    pub fn recv(&self) -> std::result::Result<CoalescedEvent, RecvError> {
+        if let Some(event) = self.ready.lock().unwrap().pop_front() {
+            return std::result::Result::Ok(event);
+        }
+        let event = self.events.recv()?;
+        std::result::Result::Ok(self.coalesce_from(event))
+    }
+
+    /// 同 recv，timeout 内没有事件时返回 RecvTimeoutError::Timeout
+    ///
+    /// 收到 DeviceRemove 后仍会等待 window，总等待时间可能超过 timeout
+    pub fn recv_timeout(&self, timeout: Duration) -> std::result::Result<CoalescedEvent, RecvTimeoutError> {
+        if let Some(event) = self.ready.lock().unwrap().pop_front() {
+            return std::result::Result::Ok(event);
+        }
+        let event = self.events.recv_timeout(timeout)?;
+        std::result::Result::Ok(self.coalesce_from(event))
+    }
+
+    /// first 为 DeviceRemove 时收集 window 内的事件并合并，返回第一个，其余留到之后的 recv
+    fn coalesce_from(&self, first: CentralEvent) -> CoalescedEvent {
+        if !matches!(first, CentralEvent::DeviceRemove { .. }) {
+            return CoalescedEvent::Event(first);
+        }
+        let deadline = Instant::now() + self.window;
+        let mut batch = vec![first];
+        while let std::result::Result::Ok(event) = self.events.recv_deadline(deadline) {
+            batch.push(event);
+        }
+        let mut ready = self.ready.lock().unwrap();
+        ready.extend(coalesce(batch, |id| self.manager.device(id).map(|d| d.container_id)));
+        ready.pop_front().expect("coalesce keeps at least one event")
+    }
+}
+
+/// 将 DeviceRemove 与其后同一 container id 的 DeviceAdd 合并为 DeviceUpdate，其他事件保持顺序
+///
+/// container_of 返回新增设备的 container id，container id 未知（nil）的设备不合并
+fn coalesce(events: Vec<CentralEvent>, container_of: impl Fn(&Uuid) -> Option<Uuid>) -> Vec<CoalescedEvent> {
+    let mut result: Vec<Option<CoalescedEvent>> = Vec::with_capacity(events.len());
+    for event in events {
+        if let CentralEvent::DeviceAdd { id, time } = &event {
+            let container_id = container_of(id).filter(|v| !v.is_nil());
+            let removed = result.iter().position(|v| matches!(v,
+                Some(CoalescedEvent::Event(CentralEvent::DeviceRemove { device, .. })) if Some(device.container_id) == container_id));
+            if let Some(index) = removed {
+                result[index] = None;
+                result.push(Some(CoalescedEvent::DeviceUpdate { id: *id, time: *time }));
+                continue;
+            }
+        }
+        result.push(Some(CoalescedEvent::Event(event)));
+    }
+    result.into_iter().flatten().collect()
+}
 
 impl Adapter {
+    /// 只管理厂商自定义 usage page（0xff00）的设备，即 DeviceFilter::vendor_defined
     pub fn new() -> Self {
+        Self::with_filter(DeviceFilter::vendor_defined())
+    }
+
+    /// 只管理满足 filter 的设备，DeviceFilter::default() 管理所有 hid 设备
+    pub fn with_filter(filter: DeviceFilter) -> Self {
+        let manager = Arc::new(Manager::new());
+        Self {  
+            manager, 
+            tracked: filter.clone(),
+            filter,
+            id_scheme: IdScheme::default(),
+            thread_handle:Arc::new(Mutex::new(None)), 
+            stop_flag:Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// 管理所有 hid 设备，但只为满足 filter 的设备产生事件
+    ///
+    /// peripherals、peripheral_by_path 等可以查到所有设备，events 只收到 filter 匹配的设备增减
+    pub fn tracking_all(filter: DeviceFilter) -> Self {
         let manager = Arc::new(Manager::new());
+        Self {
+            manager,
+            tracked: DeviceFilter::default(),
+            filter,
+            id_scheme: IdScheme::default(),
+            thread_handle:Arc::new(Mutex::new(None)),
+            stop_flag:Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// 事件队列有容量上限的 Adapter，队列满时丢弃最旧的事件
+    ///
+    /// 适合消费端可能长时间不接收事件的常驻服务，避免事件无限堆积。
+    /// capacity 为 0 时按 1 处理，被丢弃的事件数见 dropped_events
+    pub fn with_event_capacity(capacity: usize) -> Self {
+        let manager = Arc::new(Manager::with_capacity(capacity));
         Self {  
             manager, 
+            tracked: DeviceFilter::vendor_defined(),
+            filter: DeviceFilter::vendor_defined(),
+            id_scheme: IdScheme::default(),
             thread_handle:Arc::new(Mutex::new(None)), 
+            stop_flag:Arc::new(Mutex::new(None)),
         }
     }
 
+    /// 设置枚举时生成设备 id 的方式，需要在 start 之前调用
+    ///
+    /// 默认为 IdScheme::ContainerId，复合设备的多个接口需要分别管理时使用 IdScheme::ContainerPath
+    pub fn with_id_scheme(mut self, scheme: IdScheme) -> Self {
+        self.id_scheme = scheme;
+        self
+    }
+
+    /// 加载当前已连接的设备，并启动热插拔监听线程
+    ///
+    /// start 时已经连接的设备不会产生 DeviceAdd 事件，需要通过 peripherals 获取
     pub fn start(&self) -> Result<()> {
-        for item in all_hid_device()?.into_iter() {
-            if item.usage_page != 0xff00 {
-                continue;
-            }
+        for item in Self::enumerate(&self.tracked, self.id_scheme)?.into_iter() {
             self.manager.add_devices(item.id, item)?;
         }
         let manager = self.manager.clone();
+        let tracked = self.tracked.clone();
+        let filter = self.filter.clone();
+        let id_scheme = self.id_scheme;
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        let thread_stop_flag = stop_flag.clone();
         let thread_handle =  spawn(move ||{
             let func = Box::new(move || {
-                if let Err(err) = Self::usb_device_change(&manager) {
+                if let Err(err) = Self::usb_device_change(&manager, &tracked, &filter, id_scheme) {
                     println!("usb 监听错误{:?}",err);
                 }
             });
             let result = PnPDetectWindows::new(func);
-            if let Err(e) = result.detect(){
+            if let Err(e) = result.detect_until(thread_stop_flag){
                 println!("热插拔注册错误：{:?}",e);
             }
         });
+        *self.stop_flag.lock().unwrap() = Some(stop_flag);
         let mut handle = self.thread_handle.lock().unwrap();
         *handle = Some(thread_handle);
         Ok(())
     }
 
+    /// 停止热插拔监听，通知监听线程退出并等待
+    ///
+    /// 最多等待 STOP_TIMEOUT，超时后不再等待该线程。已加载的设备仍然保留
+    pub fn stop(&self) {
+        if let Some(stop_flag) = self.stop_flag.lock().unwrap().take() {
+            stop_flag.store(true, Ordering::Relaxed);
+        }
+        if let Some(thread_handle) = self.thread_handle.lock().unwrap().take() {
+            let deadline = Instant::now() + STOP_TIMEOUT;
+            while !thread_handle.is_finished() && Instant::now() < deadline {
+                sleep(Duration::from_millis(10));
+            }
+            if thread_handle.is_finished() {
+                let _ = thread_handle.join();
+            }
+        }
+    }
+
+    /// 获取事件接收端，可以在 start 之前或之后调用
+    ///
+    /// 事件在 Adapter 创建时就有缓冲队列，调用 events 之前产生的事件不会丢失，
+    /// 会按发生顺序保留到被接收为止。多次调用得到的接收端共享同一个队列，
+    /// 每个事件只会被其中一个接收端收到。
+    ///
+    /// 返回的接收端不计入空闲判断，需要在不再接收事件时让 Adapter 进入空闲状态的，使用 subscribe
     pub fn events(&self) -> Result<Receiver<CentralEvent>>{
         Ok(self.manager.event_channel())
     }
 
+    /// 同 events，但返回的接收端计入空闲判断
+    ///
+    /// 调用过 subscribe 且返回的接收端都被 drop 之后（也没有回调和等待中的 wait_for_device 等），
+    /// Adapter 进入空闲状态：热插拔时不再重新枚举设备，也不再缓存事件，peripherals 等返回空闲前最后的设备列表，
+    /// 此时 events 返回的接收端也收不到新的事件。再次调用 subscribe 时恢复，
+    /// 并立即重新枚举一次，空闲期间设备的增减会作为事件发给新的接收端。
+    pub fn subscribe(&self) -> Result<EventReceiver>{
+        let idle = self.manager.is_idle();
+        let receiver = EventReceiver {
+            receiver: self.manager.event_channel(),
+            _subscription: self.manager.subscribe(),
+        };
+        self.wake(idle)?;
+        Ok(receiver)
+    }
+
+    /// 获取合并快速拔插事件的接收端，见 CoalescedEvents::recv
+    ///
+    /// 与 events 共享同一个队列，只有通过返回值接收的事件会被合并
+    pub fn coalesce_events(&self, window: Duration) -> Result<CoalescedEvents> {
+        Ok(CoalescedEvents {
+            events: self.subscribe()?,
+            manager: self.manager.clone(),
+            window,
+            ready: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    /// 事件队列满时丢弃的事件总数，见 with_event_capacity
+    pub fn dropped_events(&self) -> usize {
+        self.manager.dropped_events()
+    }
+
+    /// 注册事件回调，每个事件都会在产生事件的线程（通常是热插拔监听线程）中调用 cb
+    ///
+    /// 可以和 events 同时使用，回调和接收端都会收到每个事件。
+    /// 注册过回调且从未调用 events 时，事件不再缓存到队列中。
+    /// cb 应尽快返回；cb 中注册的新回调从下一个事件开始调用，cb panic 不会影响其他回调和事件队列
+    pub fn on_event(&self, cb: impl Fn(CentralEvent) + Send + Sync + 'static) -> Result<()> {
+        let idle = self.manager.is_idle();
+        self.manager.add_callback(Arc::new(cb));
+        self.wake(idle)
+    }
+
+    /// 之前处于空闲状态且热插拔监听已启动时，立即重新枚举一次，补上空闲期间设备的增减
+    fn wake(&self, idle: bool) -> Result<()> {
+        if idle && self.thread_handle.lock().unwrap().is_some() {
+            Self::usb_device_change(&self.manager, &self.tracked, &self.filter, self.id_scheme)?;
+        }
+        Ok(())
+    }
+
+    /// 暂停发出事件，用于固件升级等会让设备反复重新枚举的操作
+    ///
+    /// 暂停期间热插拔监听照常更新 peripherals，但事件不会发给接收端和回调。
+    /// 暂停期间的事件不会丢弃，而是在 resume_events 时按设备合并：
+    /// 连接后又移除的设备不产生事件，移除后又重新连接的设备依次产生 DeviceRemove 和 DeviceAdd，
+    /// 其余设备只产生最后一个 DeviceAdd 或 DeviceRemove
+    pub fn pause_events(&self) {
+        self.manager.pause_events();
+    }
+
+    /// 恢复发出事件，见 pause_events
+    pub fn resume_events(&self) {
+        self.manager.resume_events();
+    }
+
+    /// 等待下一个事件，超时返回 Ok(None)
+    ///
+    /// 不计入空闲判断，空闲状态下（见 subscribe）不会收到新的事件
+    pub fn next_event_timeout(&self, timeout: Duration) -> Result<Option<CentralEvent>> {
+        match self.manager.event_channel().recv_timeout(timeout) {
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            result => Ok(Some(result?)),
+        }
+    }
+
+    /// 等待满足 predicate 的设备连接，超时返回 Error::NotFound
+    ///
+    /// 先检查当前已连接的设备，再等待新连接的设备直到匹配或超时。
+    /// 等待不会消费事件队列，也不登记为事件接收端，等待期间的事件仍然可以从 events 中收到
+    pub fn wait_for_device(&self, predicate: impl Fn(&HidDevice) -> bool, timeout: Duration) -> Result<HidDevice> {
+        // 先登记等待者再检查，避免检查之后、登记之前连接的设备被漏掉
+        let idle = self.manager.is_idle();
+        let events = self.manager.waiter();
+        if let Some(device) = self.manager.find_device(&predicate) {
+            return Ok(device);
+        }
+        self.wake(idle)?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let event = match events.recv_timeout(remaining) {
+                Err(RecvTimeoutError::Timeout) => return Err(Error::NotFound.into()),
+                result => result?,
+            };
+            if let CentralEvent::DeviceAdd { id, .. } = event {
+                match self.manager.device(&id) {
+                    Some(device) if predicate(&device) => return Ok(device),
+                    _ => continue,
+                }
+            }
+        }
+    }
+
+    /// 等待 id 对应的设备被移除，timeout 内没有移除时返回 Error::Timeout
+    ///
+    /// 设备当前不在 peripherals 中时立即返回。与 wait_for_device 相同，等待不会消费事件队列
+    pub fn wait_for_removal(&self, id: &Uuid, timeout: Duration) -> Result<()> {
+        // 先登记等待者再检查，避免检查之后、登记之前的移除被漏掉
+        let idle = self.manager.is_idle();
+        let events = self.manager.waiter();
+        if self.manager.device(id).is_none() {
+            return Ok(());
+        }
+        self.wake(idle)?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            // 不满足 filter 的设备移除时也会通知等待者
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let event = match events.recv_timeout(remaining) {
+                Err(RecvTimeoutError::Timeout) => return Err(Error::Timeout.into()),
+                result => result?,
+            };
+            if matches!(event, CentralEvent::DeviceRemove { device, .. } if device.id == *id) {
+                return Ok(());
+            }
+        }
+    }
+
     pub fn peripherals(&self) -> Result<Vec<HidDevice>> {
         Ok(self.manager.devices())
     }
 
+    /// 只返回满足 f 的设备，只克隆匹配的设备
+    pub fn peripherals_matching(&self, f: impl Fn(&HidDevice) -> bool) -> Vec<HidDevice> {
+        self.manager.devices_matching(f)
+    }
+
+    /// 逐个克隆设备交给 f，不分配整个设备列表
+    ///
+    /// 调用 f 时不持有内部的锁，f 中可以调用 Adapter 的其他方法
+    pub fn for_each_peripheral(&self, f: impl FnMut(&HidDevice)) {
+        self.manager.for_each_device(f)
+    }
+
+    /// 只返回当前可以打开的设备
+    ///
+    /// 会逐个尝试打开设备（随后立即关闭），比 peripherals 慢
+    pub fn openable_peripherals(&self) -> Vec<HidDevice> {
+        self.manager
+            .devices()
+            .into_iter()
+            .filter(|device| device.is_openable())
+            .collect()
+    }
+
     pub fn peripheral(&self, id: &Uuid) -> Result<HidDevice> {
         self.manager.device(id).ok_or(Error::NotFound.into())
     }
 
-    fn usb_device_change(manager: &Manager) -> Result<()>{
-        let current_device = all_hid_device()?;
-        let added_devices = current_device.iter().filter(|&u| (!manager.contains_device(u.id) && u.usage_page == 0xff00)).collect::<Vec<_>>();
-        for item in added_devices.into_iter(){
+    /// 与 id 对应设备属于同一物理设备（container id 相同）的其他设备，如复合键盘的厂商自定义接口
+    ///
+    /// id 对应的设备不在管理中或 container id 未知时返回空
+    pub fn siblings(&self, id: &Uuid) -> Vec<HidDevice> {
+        let container_id = match self.manager.device(id) {
+            Some(device) if !device.container_id.is_nil() => device.container_id,
+            _ => return vec![],
+        };
+        self.manager.devices_matching(|x| x.id != *id && x.container_id == container_id)
+    }
+
+    /// 根据设备路径查找设备，路径在 windows 上是稳定的设备标识
+    pub fn peripheral_by_path(&self, path: &OsStr) -> Result<HidDevice> {
+        self.manager.device_by_path(path).ok_or(Error::NotFound.into())
+    }
+
+    /// 枚举当前满足 filter 的设备，即 start 之后会产生事件的设备
+    ///
+    /// 不保存到 manager、不启动热插拔监听，可以在 start 之前调用，用于预览将要监听的设备
+    pub fn matching_devices(&self) -> Result<Vec<HidDevice>> {
+        Self::enumerate(&self.filter, self.id_scheme)
+    }
+
+    /// 枚举满足 tracked 的设备，打开每个设备最多等待 ENUM_OPEN_TIMEOUT
+    ///
+    /// tracked 设置了 manufacturer_prefix 时读取设备的字符串，见 DeviceFilter::matches_loading_strings
+    fn enumerate(tracked: &DeviceFilter, id_scheme: IdScheme) -> Result<Vec<HidDevice>> {
+        let options = OpenOptions { timeout: Some(ENUM_OPEN_TIMEOUT), id_scheme, ..Default::default() };
+        let mut devices = all_hid_device_with_options(&options)?;
+        devices.retain_mut(|u| tracked.matches_loading_strings(u));
+        Ok(devices)
+    }
+
+    /// 满足 tracked 的设备保存到 manager，其中满足 filter 的才产生事件
+    ///
+    /// 热插拔线程和 wake 可能同时调用，对比前加锁，避免同一设备产生两次事件
+    fn usb_device_change(manager: &Manager, tracked: &DeviceFilter, filter: &DeviceFilter, id_scheme: IdScheme) -> Result<()>{
+        let _changing = manager.lock_changes();
+        // 没有接收端关心事件时跳过枚举，恢复时由 subscribe、on_event 重新枚举
+        if manager.is_idle() {
+            manager.clear_events();
+            return Ok(());
+        }
+        let current_device = Self::enumerate(tracked, id_scheme)?;
+        let (added_devices, mut removed_devices) = diff_devices(&manager.devices(), &current_device);
+        // 枚举时打开超时（或失败）而被跳过的设备仍然连接着，不算移除
+        if !removed_devices.is_empty() {
+            let present = present_device_paths()?;
+            removed_devices.retain(|item| !present.contains(&item.path));
+        }
+        // 不满足 filter 的设备不产生事件，只通知等待者
+        for mut item in added_devices.into_iter(){
+            let matched = filter.matches_loading_strings(&mut item);
             manager.add_devices(item.id,  item.clone())?;
-            manager.emit(CentralEvent::DeviceAdd(item.id));
-        }
-        // 计算移除的设备 
-        let new_key = current_device.iter().map(|d| d.id.clone()).collect::<Vec<_>>();
-        let current_keys = manager.device_keys();
-        let removed_keys = current_keys.iter().filter(|&u| !new_key.contains(u)).collect::<Vec<_>>();
-        for key in removed_keys {
-            match manager.remove_device(key.clone()) {
-                Some((_, val)) => {
-                    manager.emit(CentralEvent::DeviceRemove(val));
+            if matched {
+                manager.emit(DeviceChange::Add(item.id));
+            } else {
+                manager.notify_waiters(DeviceChange::Add(item.id));
+            }
+        }
+        for item in removed_devices {
+            match manager.remove_device(item.id) {
+                Some((_, val)) if filter.matches(&val) => {
+                    manager.emit(DeviceChange::Remove(val));
                 }
+                Some((_, val)) => manager.notify_waiters(DeviceChange::Remove(val)),
                 None => continue,
             }
         }
         Ok(())
     }
-}   
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_before_subscribe_test() {
+        let adapter = Adapter::new();
+        let id = Uuid::new_v4();
+        adapter.manager.emit(DeviceChange::Add(id));
+        let read = adapter.events().unwrap();
+        match read.try_recv().unwrap() {
+            CentralEvent::DeviceAdd { id: v, .. } => assert_eq!(v, id),
+            _ => panic!("unexpected event"),
+        }
+    }
+
+    #[test]
+    fn next_event_timeout_test() {
+        let adapter = Adapter::new();
+        assert!(adapter.next_event_timeout(Duration::from_millis(10)).unwrap().is_none());
+        let id = Uuid::new_v4();
+        adapter.manager.emit(DeviceChange::Add(id));
+        assert!(matches!(adapter.next_event_timeout(Duration::from_millis(10)).unwrap(), Some(CentralEvent::DeviceAdd { id: v, .. }) if v == id));
+    }
+
+    #[test]
+    fn pause_events_test() {
+        let adapter = Adapter::new();
+        let read = adapter.events().unwrap();
+        adapter.pause_events();
+        let (added, replugged, removed) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let mut replugged_device = HidDevice::default();
+        replugged_device.id = replugged;
+        let mut removed_device = HidDevice::default();
+        removed_device.id = removed;
+        adapter.manager.emit(DeviceChange::Add(added));
+        adapter.manager.emit(DeviceChange::Remove(replugged_device));
+        adapter.manager.emit(DeviceChange::Remove(HidDevice::new(added, "a".into())));
+        adapter.manager.emit(DeviceChange::Add(replugged));
+        adapter.manager.emit(DeviceChange::Remove(removed_device));
+        assert!(read.try_recv().is_err());
+        adapter.resume_events();
+        assert!(matches!(read.try_recv().unwrap(), CentralEvent::DeviceRemove { device: v, .. } if v.id == replugged));
+        assert!(matches!(read.try_recv().unwrap(), CentralEvent::DeviceAdd { id: v, .. } if v == replugged));
+        assert!(matches!(read.try_recv().unwrap(), CentralEvent::DeviceRemove { device: v, .. } if v.id == removed));
+        assert!(read.try_recv().is_err());
+        adapter.manager.emit(DeviceChange::Add(added));
+        assert!(matches!(read.try_recv().unwrap(), CentralEvent::DeviceAdd { id: v, .. } if v == added));
+    }
+
+    #[test]
+    fn pause_in_callback_test() {
+        let adapter = Adapter::new();
+        let read = adapter.events().unwrap();
+        // resume_events 发出事件时不持有锁，回调中可以再次暂停
+        let manager = Arc::downgrade(&adapter.manager);
+        adapter.on_event(move |_| {
+            if let Some(manager) = manager.upgrade() {
+                manager.pause_events();
+            }
+        }).unwrap();
+        adapter.pause_events();
+        adapter.manager.emit(DeviceChange::Add(Uuid::new_v4()));
+        adapter.resume_events();
+        assert!(read.try_recv().is_ok());
+        adapter.manager.emit(DeviceChange::Add(Uuid::new_v4()));
+        assert!(read.try_recv().is_err());
+    }
+
+    #[test]
+    fn idle_after_receivers_dropped_test() {
+        let adapter = Adapter::new();
+        assert!(!adapter.manager.is_idle());
+        let read = adapter.subscribe().unwrap();
+        let read2 = read.clone();
+        drop(read);
+        assert!(!adapter.manager.is_idle());
+        drop(read2);
+        assert!(adapter.manager.is_idle());
+        // events 返回的接收端不计入空闲判断
+        let _events = adapter.events().unwrap();
+        assert!(adapter.manager.is_idle());
+        adapter.manager.emit(DeviceChange::Add(Uuid::new_v4()));
+        Adapter::usb_device_change(&adapter.manager, &adapter.tracked, &adapter.filter, adapter.id_scheme).unwrap();
+        let read = adapter.subscribe().unwrap();
+        assert!(!adapter.manager.is_idle());
+        assert!(read.try_recv().is_err());
+    }
+
+    #[test]
+    fn waiter_test() {
+        let adapter = Adapter::new();
+        let read = adapter.subscribe().unwrap();
+        let waiter = adapter.manager.waiter();
+        drop(read);
+        // 等待者存活时不进入空闲状态
+        assert!(!adapter.manager.is_idle());
+        let id = Uuid::new_v4();
+        adapter.manager.emit(DeviceChange::Add(id));
+        assert!(matches!(waiter.try_recv().unwrap(), CentralEvent::DeviceAdd { id: v, .. } if v == id));
+        // 等待者不消费事件队列
+        let read = adapter.subscribe().unwrap();
+        assert!(matches!(read.try_recv().unwrap(), CentralEvent::DeviceAdd { id: v, .. } if v == id));
+        drop(read);
+        drop(waiter);
+        assert!(adapter.manager.is_idle());
+    }
+
+    #[test]
+    fn wait_for_device_test() {
+        let adapter = Adapter::new();
+        assert!(adapter.wait_for_device(|_| true, Duration::from_millis(10)).is_err());
+        let device = HidDevice::new(Uuid::new_v4(), "test".into());
+        adapter.manager.add_devices(device.id, device.clone()).unwrap();
+        adapter.manager.emit(DeviceChange::Add(device.id));
+        let found = adapter.wait_for_device(|x| x.path == device.path, Duration::from_millis(10)).unwrap();
+        assert_eq!(found.id, device.id);
+        // 等待期间连接的设备
+        let later = HidDevice::new(Uuid::new_v4(), "later".into());
+        let manager = adapter.manager.clone();
+        let added = later.clone();
+        let handle = spawn(move || {
+            sleep(Duration::from_millis(20));
+            manager.add_devices(added.id, added.clone()).unwrap();
+            manager.emit(DeviceChange::Add(added.id));
+        });
+        let found = adapter.wait_for_device(|x| x.path == later.path, Duration::from_secs(1)).unwrap();
+        assert_eq!(found.id, later.id);
+        handle.join().unwrap();
+        // 等待不消费事件，接收端仍然收到两个 DeviceAdd
+        let read = adapter.events().unwrap();
+        assert_eq!(read.try_iter().count(), 2);
+    }
+
+    #[test]
+    fn wait_for_device_keeps_hotplug_test() {
+        let adapter = Adapter::new();
+        let read = adapter.subscribe().unwrap();
+        assert!(adapter.wait_for_device(|_| false, Duration::from_millis(10)).is_err());
+        drop(read);
+        let read = adapter.subscribe().unwrap();
+        assert!(adapter.wait_for_removal(&Uuid::new_v4(), Duration::from_millis(10)).is_ok());
+        // 等待结束后用户的接收端仍然存活，热插拔照常处理
+        assert!(!adapter.manager.is_idle());
+        let device = HidDevice::new(Uuid::new_v4(), "test".into());
+        adapter.manager.add_devices(device.id, device.clone()).unwrap();
+        Adapter::usb_device_change(&adapter.manager, &adapter.tracked, &adapter.filter, adapter.id_scheme).unwrap();
+        assert!(adapter.peripheral(&device.id).is_err());
+        assert!(matches!(read.try_recv().unwrap(), CentralEvent::DeviceRemove { device: v, .. } if v.id == device.id));
+    }
+
+    #[test]
+    fn matching_devices_test() {
+        let adapter = Adapter::tracking_all(DeviceFilter::vendor_defined());
+        let devices = adapter.matching_devices().unwrap();
+        assert!(devices.iter().all(|x| DeviceFilter::vendor_defined().matches(x)));
+        assert!(adapter.peripherals().unwrap().is_empty());
+        assert!(adapter.thread_handle.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn wait_for_removal_test() {
+        let adapter = Adapter::new();
+        let device = HidDevice::new(Uuid::new_v4(), "test".into());
+        assert!(adapter.wait_for_removal(&device.id, Duration::from_millis(10)).is_ok());
+        adapter.manager.add_devices(device.id, device.clone()).unwrap();
+        let result = adapter.wait_for_removal(&device.id, Duration::from_millis(10));
+        assert!(matches!(result.unwrap_err().downcast_ref::<Error>(), Some(Error::Timeout)));
+        let manager = adapter.manager.clone();
+        let removed = device.clone();
+        let handle = spawn(move || {
+            sleep(Duration::from_millis(20));
+            manager.remove_device(removed.id);
+            manager.notify_waiters(DeviceChange::Remove(removed));
+        });
+        assert!(adapter.wait_for_removal(&device.id, Duration::from_secs(1)).is_ok());
+        handle.join().unwrap();
+        assert!(adapter.wait_for_removal(&device.id, Duration::from_millis(10)).is_ok());
+    }
+
+    #[test]
+    fn peripherals_matching_test() {
+        let adapter = Adapter::new();
+        for path in ["a", "b", "c"] {
+            let device = HidDevice::new(Uuid::new_v4(), path.into());
+            adapter.manager.add_devices(device.id, device).unwrap();
+        }
+        let matched = adapter.peripherals_matching(|x| x.path != "b");
+        assert_eq!(matched.len(), 2);
+        assert!(matched.iter().all(|x| x.path != "b"));
+    }
+
+    #[test]
+    fn event_capacity_test() {
+        let adapter = Adapter::with_event_capacity(2);
+        let ids = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+        for id in ids.iter() {
+            adapter.manager.emit(DeviceChange::Add(*id));
+        }
+        let read = adapter.events().unwrap();
+        let received = read.try_iter().map(|v| match v {
+            CentralEvent::DeviceAdd { id, .. } => id,
+            _ => panic!("unexpected event"),
+        }).collect::<Vec<_>>();
+        assert_eq!(received, ids[1..].to_vec());
+        assert_eq!(adapter.dropped_events(), 1);
+        let adapter = Adapter::with_event_capacity(0);
+        for id in ids.iter() {
+            adapter.manager.emit(DeviceChange::Add(*id));
+        }
+        assert!(matches!(adapter.events().unwrap().try_recv().unwrap(), CentralEvent::DeviceAdd { id: v, .. } if v == ids[2]));
+        assert_eq!(adapter.dropped_events(), 2);
+    }
+
+    #[test]
+    fn tracking_all_test() {
+        let adapter = Adapter::tracking_all(DeviceFilter::vendor_defined());
+        assert_eq!(adapter.tracked, DeviceFilter::default());
+        assert_eq!(adapter.filter, DeviceFilter::vendor_defined());
+        let device = HidDevice::new(Uuid::new_v4(), "test".into());
+        adapter.manager.add_devices(device.id, device.clone()).unwrap();
+        let read = adapter.events().unwrap();
+        Adapter::usb_device_change(&adapter.manager, &adapter.tracked, &adapter.filter, adapter.id_scheme).unwrap();
+        // 移除的设备不满足 filter，不产生事件
+        assert!(read.try_recv().is_err());
+        assert!(adapter.peripheral(&device.id).is_err());
+    }
+
+    #[test]
+    fn keeps_present_devices_test() {
+        let adapter = Adapter::new();
+        // 仍然连接、但这次枚举没有得到的设备（如打开超时）
+        let device = crate::hid_device::all_hid_device().unwrap().into_iter().find(|x| !adapter.tracked.matches(x)).unwrap();
+        adapter.manager.add_devices(device.id, device.clone()).unwrap();
+        let read = adapter.events().unwrap();
+        Adapter::usb_device_change(&adapter.manager, &adapter.tracked, &adapter.filter, adapter.id_scheme).unwrap();
+        assert!(read.try_recv().is_err());
+        assert!(adapter.peripheral(&device.id).is_ok());
+    }
+
+    #[test]
+    fn id_scheme_test() {
+        assert_eq!(Adapter::new().id_scheme, IdScheme::ContainerId);
+        let adapter = Adapter::new().with_id_scheme(IdScheme::ContainerPath);
+        for device in adapter.matching_devices().unwrap() {
+            assert_ne!(device.id, device.container_id);
+        }
+    }
+
+    #[test]
+    fn stop_without_start_test() {
+        let adapter = Adapter::new();
+        let clone = adapter.clone();
+        drop(clone);
+        adapter.stop();
+        assert!(adapter.thread_handle.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn on_event_test() {
+        let adapter = Adapter::new();
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        adapter.on_event(move |event| sender.send(event).unwrap()).unwrap();
+        let read = adapter.subscribe().unwrap();
+        let id = Uuid::new_v4();
+        adapter.manager.emit(DeviceChange::Add(id));
+        assert!(matches!(receiver.try_recv().unwrap(), CentralEvent::DeviceAdd { id: v, .. } if v == id));
+        assert!(matches!(read.try_recv().unwrap(), CentralEvent::DeviceAdd { id: v, .. } if v == id));
+        drop(read);
+        assert!(!adapter.manager.is_idle());
+    }
+
+    #[test]
+    fn on_event_reentrant_test() {
+        let adapter = Adapter::new();
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        // 回调中注册回调不会死锁，panic 的回调不影响其他回调
+        let manager = Arc::downgrade(&adapter.manager);
+        adapter.on_event(move |_| {
+            let sender = sender.clone();
+            if let Some(manager) = manager.upgrade() {
+                manager.add_callback(Arc::new(move |event| sender.send(event).unwrap()));
+            }
+        }).unwrap();
+        adapter.on_event(|_| panic!("callback panic")).unwrap();
+        let id = Uuid::new_v4();
+        adapter.manager.emit(DeviceChange::Add(id));
+        adapter.manager.emit(DeviceChange::Add(id));
+        assert!(matches!(receiver.try_recv().unwrap(), CentralEvent::DeviceAdd { id: v, .. } if v == id));
+    }
+
+    #[test]
+    fn siblings_test() {
+        let adapter = Adapter::new();
+        let container_id = Uuid::new_v4();
+        let mut ids = vec![];
+        for (path, container) in [("a", container_id), ("b", container_id), ("c", Uuid::new_v4())] {
+            let mut device = HidDevice::new(Uuid::new_v4(), path.into());
+            device.container_id = container;
+            ids.push(device.id);
+            adapter.manager.add_devices(device.id, device).unwrap();
+        }
+        let siblings = adapter.siblings(&ids[0]);
+        assert_eq!(siblings.len(), 1);
+        assert_eq!(siblings[0].id, ids[1]);
+        assert!(adapter.siblings(&ids[2]).is_empty());
+        assert!(adapter.siblings(&Uuid::new_v4()).is_empty());
+    }
+
+    #[test]
+    fn coalesce_test() {
+        let container_id = Uuid::new_v4();
+        let mut removed = HidDevice::new(Uuid::new_v4(), "a".into());
+        removed.container_id = container_id;
+        let mut other = HidDevice::new(Uuid::new_v4(), "b".into());
+        other.container_id = Uuid::new_v4();
+        let added = Uuid::new_v4();
+        let unknown = Uuid::new_v4();
+        let time = SystemTime::now();
+        let events = vec![
+            CentralEvent::DeviceRemove { device: removed, time },
+            CentralEvent::DeviceRemove { device: other.clone(), time },
+            CentralEvent::DeviceAdd { id: unknown, time },
+            CentralEvent::DeviceAdd { id: added, time },
+        ];
+        let result = coalesce(events, |id| if *id == added { Some(container_id) } else { None });
+        assert_eq!(result.len(), 3);
+        assert!(matches!(&result[0], CoalescedEvent::Event(CentralEvent::DeviceRemove { device: v, .. }) if v.id == other.id));
+        assert!(matches!(result[1], CoalescedEvent::Event(CentralEvent::DeviceAdd { id: v, .. }) if v == unknown));
+        assert!(matches!(result[2], CoalescedEvent::DeviceUpdate { id: v, .. } if v == added));
+    }
+
+    #[test]
+    fn coalesce_events_test() {
+        let adapter = Adapter::new();
+        let read = adapter.coalesce_events(Duration::from_millis(20)).unwrap();
+        let mut device = HidDevice::new(Uuid::new_v4(), "a".into());
+        device.container_id = Uuid::new_v4();
+        adapter.manager.add_devices(device.id, device.clone()).unwrap();
+        adapter.manager.emit(DeviceChange::Remove(device.clone()));
+        adapter.manager.emit(DeviceChange::Add(device.id));
+        assert!(matches!(read.recv().unwrap(), CoalescedEvent::DeviceUpdate { id: v, .. } if v == device.id));
+        assert!(read.recv_timeout(Duration::from_millis(10)).is_err());
+    }
+
+    #[test]
+    fn for_each_peripheral_test() {
+        let adapter = Adapter::new();
+        for path in ["a", "b", "c"] {
+            let device = HidDevice::new(Uuid::new_v4(), path.into());
+            adapter.manager.add_devices(device.id, device).unwrap();
+        }
+        let mut paths = vec![];
+        adapter.for_each_peripheral(|device| {
+            // 回调中访问 manager 不会死锁
+            adapter.manager.remove_device(device.id);
+            paths.push(device.path.clone());
+        });
+        paths.sort();
+        assert_eq!(paths, vec!["a", "b", "c"]);
+        assert!(adapter.peripherals().unwrap().is_empty());
+    }
+}
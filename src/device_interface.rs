@@ -1,11 +1,10 @@
-use std::{ffi::OsString, mem::size_of, os::windows::prelude::OsStringExt};
-use anyhow::{bail};
+use std::{ffi::{OsStr, OsString}, mem::size_of, os::windows::prelude::OsStringExt};
 use windows::{
     core::GUID,
     Win32::{
         Devices::{
             DeviceAndDriverInstallation::{
-                SetupDiDestroyDeviceInfoList, SetupDiEnumDeviceInterfaces, SetupDiGetClassDevsW,
+                SetupDiCreateDeviceInfoList, SetupDiDestroyDeviceInfoList, SetupDiEnumDeviceInterfaces, SetupDiOpenDeviceInterfaceW, SetupDiGetClassDevsW,
                 SetupDiGetDeviceInterfaceDetailW, SetupDiGetDevicePropertyW, DIGCF_ALLCLASSES,
                 DIGCF_DEVICEINTERFACE, DIGCF_PRESENT, SP_DEVICE_INTERFACE_DATA,
                 SP_DEVICE_INTERFACE_DETAIL_DATA_W, SP_DEVINFO_DATA,HDEVINFO,
@@ -18,7 +17,7 @@ use windows::{
     },
 };
 
-use super::Error;
+use super::{Error, Result};
 
 /*
     Device Interface:
@@ -34,14 +33,16 @@ use super::Error;
 pub struct DeviceInfoSet(HDEVINFO);
 
 impl DeviceInfoSet {
-    pub fn new(class: Option<&GUID>) -> anyhow::Result<Self> {
+    /// present_only 为 false 时包括系统记录过但当前未连接的设备
+    pub fn new(class: Option<&GUID>, present_only: bool) -> Result<Self> {
+        let present = if present_only { DIGCF_PRESENT } else { 0 };
         let device_info_set = if let Some(class) = class {
             unsafe {
                 SetupDiGetClassDevsW(
                     class,
                     ::windows::core::PCWSTR::default(),
                     ::windows::Win32::Foundation::HWND::default(),
-                    DIGCF_DEVICEINTERFACE | DIGCF_PRESENT,
+                    DIGCF_DEVICEINTERFACE | present,
                 )
             }
         } else {
@@ -50,7 +51,7 @@ impl DeviceInfoSet {
                     std::ptr::null(),
                     ::windows::core::PCWSTR::default(),
                     ::windows::Win32::Foundation::HWND::default(),
-                    DIGCF_ALLCLASSES | DIGCF_DEVICEINTERFACE | DIGCF_PRESENT,
+                    DIGCF_ALLCLASSES | DIGCF_DEVICEINTERFACE | present,
                 )
             }
         };
@@ -59,14 +60,64 @@ impl DeviceInfoSet {
                 if !info.is_invalid() {
                     return Ok(DeviceInfoSet(info));
                 }
-                bail!("not find");
+                return Err(Error::NotFound);
             },
             Err(e) => {
-                bail!(e);
+                return Err(e.into());
             }
         }
     }
 
+    /// 只包含设备实例 id 为 instance_id（如 `HID\VID_1234&PID_5678\...`）的已连接设备
+    pub fn for_instance(class: &GUID, instance_id: &str) -> Result<Self> {
+        let info = unsafe {
+            SetupDiGetClassDevsW(
+                class,
+                instance_id,
+                ::windows::Win32::Foundation::HWND::default(),
+                DIGCF_DEVICEINTERFACE | DIGCF_PRESENT,
+            )
+        }?;
+        if info.is_invalid() {
+            return Err(Error::NotFound);
+        }
+        Ok(DeviceInfoSet(info))
+    }
+
+    /// 只包含设备接口路径为 path 的设备，返回集合和该设备的 SP_DEVINFO_DATA
+    ///
+    /// 适用于任意类型的设备接口，路径不存在时返回 Error::NotFound
+    pub fn for_path(path: &OsStr) -> Result<(Self, SP_DEVINFO_DATA)> {
+        let set = DeviceInfoSet(unsafe {
+            SetupDiCreateDeviceInfoList(std::ptr::null(), ::windows::Win32::Foundation::HWND::default())
+        }?);
+        unsafe {
+            let mut device_interface_data: SP_DEVICE_INTERFACE_DATA = std::mem::zeroed();
+            device_interface_data.cbSize = size_of::<SP_DEVICE_INTERFACE_DATA>() as u32;
+            if !SetupDiOpenDeviceInterfaceW(set.0, path, 0, &mut device_interface_data).as_bool() {
+                return Err(Error::NotFound);
+            }
+            let mut device_info_data = SP_DEVINFO_DATA {
+                cbSize: size_of::<SP_DEVINFO_DATA>() as u32,
+                ..std::mem::zeroed()
+            };
+            // 只需要 SP_DEVINFO_DATA，不取路径，缓冲区不足是预期的
+            if !SetupDiGetDeviceInterfaceDetailW(
+                set.0,
+                &device_interface_data,
+                std::ptr::null_mut(),
+                0,
+                std::ptr::null_mut(),
+                &mut device_info_data,
+            ).as_bool()
+                && GetLastError() != ERROR_INSUFFICIENT_BUFFER
+            {
+                return Err(Error::win32());
+            }
+            Ok((set, device_info_data))
+        }
+    }
+
     pub fn iter_device_interfaces(&self, class: GUID) -> DeviceInterfaceIterator {
         DeviceInterfaceIterator {
             idx: 0,
@@ -75,7 +126,7 @@ impl DeviceInfoSet {
         }
     }
 
-    pub fn get_container_id(&self, device_info_data: &SP_DEVINFO_DATA) -> anyhow::Result<GUID> {
+    pub fn get_container_id(&self, device_info_data: &SP_DEVINFO_DATA) -> Result<GUID> {
         let mut container_id = unsafe { std::mem::zeroed() };
         if !unsafe {
             SetupDiGetDevicePropertyW(
@@ -90,7 +141,7 @@ impl DeviceInfoSet {
             )
         }.as_bool()
         {
-            bail!(Error::win32())
+            Err(Error::win32())
         } else {
             Ok(container_id)
         }
@@ -115,15 +166,26 @@ impl Iterator for DeviceInterfaceIterator<'_> {
     type Item = (OsString, SP_DEVINFO_DATA);
 
     fn next(&mut self) -> Option<Self::Item> {
+        let item = self.device_info_set.device_interface(&self.class, self.idx)?;
+        self.idx += 1;
+        Some(item)
+    }
+}
+
+impl DeviceInfoSet {
+    /// 第 idx 个 class 类型的设备接口，没有更多接口时返回 None
+    ///
+    /// 供需要自己持有 DeviceInfoSet 的迭代器逐个获取接口，见 DeviceInterfaceIterator
+    pub fn device_interface(&self, class: &GUID, idx: u32) -> Option<(OsString, SP_DEVINFO_DATA)> {
         unsafe {
             let mut device_interface_data: SP_DEVICE_INTERFACE_DATA = std::mem::zeroed();
             device_interface_data.cbSize = size_of::<SP_DEVICE_INTERFACE_DATA>() as u32;
 
             if !SetupDiEnumDeviceInterfaces(
-                self.device_info_set.0,
+                self.0,
                 std::ptr::null(),
-                &self.class,
-                self.idx,
+                class,
+                idx,
                 &mut device_interface_data,
             ).as_bool()
             {
@@ -134,8 +196,6 @@ impl Iterator for DeviceInterfaceIterator<'_> {
                 }
             }
 
-            self.idx += 1;
-
             let mut size = 0;
 
             let mut device_info_data = SP_DEVINFO_DATA {
@@ -144,7 +204,7 @@ impl Iterator for DeviceInterfaceIterator<'_> {
             };
 
             if !SetupDiGetDeviceInterfaceDetailW(
-                self.device_info_set.0,
+                self.0,
                 &device_interface_data,
                 std::ptr::null_mut(),
                 0,
@@ -156,14 +216,15 @@ impl Iterator for DeviceInterfaceIterator<'_> {
                 panic!("{}", Error::win32());
             }
 
-            let mut buffer = Vec::<u8>::with_capacity(size as usize);
+            // 用 u32 分配，保证 cbSize 满足 SP_DEVICE_INTERFACE_DETAIL_DATA_W 的对齐要求
+            let mut buffer = vec![0u32; (size as usize + size_of::<u32>() - 1) / size_of::<u32>()];
             let detail = buffer.as_mut_ptr() as *mut SP_DEVICE_INTERFACE_DETAIL_DATA_W;
             (*detail).cbSize = size_of::<SP_DEVICE_INTERFACE_DETAIL_DATA_W>() as u32;
 
             if !SetupDiGetDeviceInterfaceDetailW(
-                self.device_info_set.0,
+                self.0,
                 &device_interface_data,
-                buffer.as_mut_ptr() as _,
+                detail,
                 size,
                 std::ptr::null_mut(),
                 std::ptr::null_mut(),
@@ -172,12 +233,47 @@ impl Iterator for DeviceInterfaceIterator<'_> {
                 panic!("{}", Error::win32());
             }
 
-            let device_interface_name = OsString::from_wide(std::slice::from_raw_parts(
-                (*detail).DevicePath.as_ptr(),
-                (size as usize - size_of::<u32>()) / 2,
-            ));
+            let device_interface_name = device_path_from_detail(detail, size as usize);
 
             Some((device_interface_name, device_info_data))
         }
     }
+}
+
+/// 从 SP_DEVICE_INTERFACE_DETAIL_DATA_W 中解析设备路径
+///
+/// size 为整个缓冲区的字节数，其中可能包含结尾的 \0，需要去掉，否则路径比较会失败
+unsafe fn device_path_from_detail(detail: *const SP_DEVICE_INTERFACE_DETAIL_DATA_W, size: usize) -> OsString {
+    let path = std::slice::from_raw_parts(
+        (*detail).DevicePath.as_ptr(),
+        size.saturating_sub(size_of::<u32>()) / 2,
+    );
+    let len = path.iter().position(|&c| c == 0).unwrap_or(path.len());
+    OsString::from_wide(&path[..len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detail_buffer_alignment_test() {
+        assert!(std::mem::align_of::<SP_DEVICE_INTERFACE_DETAIL_DATA_W>() <= std::mem::align_of::<u32>());
+    }
+
+    #[test]
+    fn device_path_from_detail_test() {
+        let path = r"\\?\hid#vid_1234&pid_5678&mi_01#7&1a2b3c4d&0&0000#{4d1e55b2-f16f-11cf-88cb-001111000030}";
+        let wide: Vec<u16> = path.encode_utf16().chain(std::iter::once(0)).collect();
+        let size = size_of::<u32>() + wide.len() * 2;
+        // 用 u32 分配保证 cbSize 的对齐
+        let mut buffer = vec![0u32; (size + 3) / 4];
+        unsafe {
+            let detail = buffer.as_mut_ptr() as *mut SP_DEVICE_INTERFACE_DETAIL_DATA_W;
+            (*detail).cbSize = size_of::<SP_DEVICE_INTERFACE_DETAIL_DATA_W>() as u32;
+            std::ptr::copy_nonoverlapping(wide.as_ptr(), (*detail).DevicePath.as_mut_ptr(), wide.len());
+            let decoded = device_path_from_detail(detail, size);
+            assert_eq!(decoded, OsString::from(path));
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,82 @@
+use std::ffi::OsStr;
+
+/// 从设备接口路径中解析出的设备标识
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceIds {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub interface_number: Option<u8>,      // mi_xx，复合设备的接口号
+    pub container_hint: Option<String>,    // 路径中的实例段，同一物理设备的接口通常相同
+}
+
+/// 不打开设备，直接从路径中解析 VID/PID/接口号
+///
+/// 支持 USB 路径（`vid_046d&pid_c52b&mi_02`）和蓝牙路径（`vid&0002046d_pid&b023`），
+/// 缺少 VID 或 PID 时返回 None
+pub fn parse_device_path(path: &OsStr) -> Option<DeviceIds> {
+    let path = path.to_string_lossy().to_lowercase();
+    let vendor_id = hex_token(&path, "vid_", 4).or_else(|| hex_token(&path, "vid&", 8))? as u16;
+    let product_id = hex_token(&path, "pid_", 4).or_else(|| hex_token(&path, "pid&", 4))? as u16;
+    let interface_number = hex_token(&path, "mi_", 2).map(|v| v as u8);
+    let container_hint = path
+        .split('#')
+        .nth(2)
+        .filter(|v| !v.is_empty())
+        .map(String::from);
+    Some(DeviceIds {
+        vendor_id,
+        product_id,
+        interface_number,
+        container_hint,
+    })
+}
+
+/// 路径中是否带有 XInput 设备的 `IG_` 标记
+///
+/// 这类手柄同时由 XInput 驱动，应通过 XInput 读取，raw HID 只能读到部分数据
+pub fn is_xinput_path(path: &OsStr) -> bool {
+    path.to_string_lossy().to_lowercase().contains("&ig_")
+}
+
+/// 读取 token 之后 len 位十六进制数
+fn hex_token(path: &str, token: &str, len: usize) -> Option<u32> {
+    let start = path.find(token)? + token.len();
+    let value = path.get(start..start + len)?;
+    u32::from_str_radix(value, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_usb_path_test() {
+        let path = OsStr::new(r"\\?\HID#VID_046D&PID_C52B&MI_02&Col01#8&2b9e0a3&0&0001#{4d1e55b2-f16f-11cf-88cb-001111000030}");
+        let ids = parse_device_path(path).unwrap();
+        assert_eq!(ids.vendor_id, 0x046d);
+        assert_eq!(ids.product_id, 0xc52b);
+        assert_eq!(ids.interface_number, Some(2));
+        assert_eq!(ids.container_hint.as_deref(), Some("8&2b9e0a3&0&0001"));
+    }
+
+    #[test]
+    fn parse_bluetooth_path_test() {
+        let path = OsStr::new(r"\\?\hid#{00001124-0000-1000-8000-00805f9b34fb}_vid&0002046d_pid&b023&col01#9&1b4a7e4f&0&0000#{4d1e55b2-f16f-11cf-88cb-001111000030}");
+        let ids = parse_device_path(path).unwrap();
+        assert_eq!(ids.vendor_id, 0x046d);
+        assert_eq!(ids.product_id, 0xb023);
+        assert_eq!(ids.interface_number, None);
+    }
+
+    #[test]
+    fn parse_invalid_path_test() {
+        assert_eq!(parse_device_path(OsStr::new(r"\\?\hid#something_else")), None);
+        assert_eq!(parse_device_path(OsStr::new("vid_12")), None);
+    }
+
+    #[test]
+    fn xinput_path_test() {
+        assert!(is_xinput_path(OsStr::new(r"\\?\HID#VID_045E&PID_028E&IG_00#8&1f5e2b1&0&0000#{4d1e55b2-f16f-11cf-88cb-001111000030}")));
+        assert!(!is_xinput_path(OsStr::new(r"\\?\HID#VID_046D&PID_C52B&MI_02&Col01#8&2b9e0a3&0&0001#{4d1e55b2-f16f-11cf-88cb-001111000030}")));
+    }
+}
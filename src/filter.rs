@@ -0,0 +1,133 @@
+use super::hid_device::HidDevice;
+
+/// 设备过滤条件，为 None 的字段不参与匹配，Default 匹配所有设备
+///
+/// ```no_run
+/// use usb_manager::filter::DeviceFilter;
+///
+/// let filter = DeviceFilter {
+///     vendor_id: Some(0x1234),
+///     usage_page: Some(0xff00),
+///     ..Default::default()
+/// };
+/// let devices = usb_manager::hid_device::all_hid_device_matching(&filter).unwrap();
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DeviceFilter {
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+    pub usage_page: Option<u16>,
+    pub usage: Option<u16>,
+    pub serial: Option<String>,
+    /// 厂商字符串前缀，不区分大小写
+    ///
+    /// 枚举得到的设备不读取字符串，matches 只比较已经读取的 manufacturer，
+    /// 需要先调用 HidDevice::load_strings，或使用 matches_loading_strings
+    pub manufacturer_prefix: Option<String>,
+}
+
+impl DeviceFilter {
+    /// 厂商自定义 usage page（0xff00）的设备，Adapter 默认使用该过滤条件
+    pub fn vendor_defined() -> Self {
+        Self {
+            usage_page: Some(0xff00),
+            ..Default::default()
+        }
+    }
+
+    /// 设备是否满足所有已设置的条件
+    ///
+    /// usage_page/usage 与顶层集合或任意一个 application 集合（HidDevice::usages）匹配即可。
+    /// 只比较设备中已有的字段，不打开设备
+    pub fn matches(&self, dev: &HidDevice) -> bool {
+        self.matches_ids(dev)
+            && self.manufacturer_prefix.as_ref().map_or(true, |v| dev.manufacturer.to_lowercase().starts_with(&v.to_lowercase()))
+    }
+
+    /// 同 matches，设置了 manufacturer_prefix 且设备还没有读取字符串时，先调用 HidDevice::load_strings
+    ///
+    /// 其他条件不满足时不会打开设备，读取字符串失败的设备不匹配
+    pub fn matches_loading_strings(&self, dev: &mut HidDevice) -> bool {
+        if self.manufacturer_prefix.is_none() || !dev.manufacturer.is_empty() {
+            return self.matches(dev);
+        }
+        self.matches_ids(dev) && dev.load_strings().is_ok() && self.matches(dev)
+    }
+
+    /// 除 manufacturer_prefix 之外的条件
+    fn matches_ids(&self, dev: &HidDevice) -> bool {
+        self.vendor_id.map_or(true, |v| v == dev.vendor_id)
+            && self.product_id.map_or(true, |v| v == dev.product_id)
+            && (self.matches_usage(dev.usage_page, dev.usage)
+                || dev.usages.iter().any(|&(usage_page, usage)| self.matches_usage(usage_page, usage)))
+            && self.serial.as_ref().map_or(true, |v| *v == dev.serial)
+    }
+
+    fn matches_usage(&self, usage_page: u16, usage: u16) -> bool {
+        self.usage_page.map_or(true, |v| v == usage_page) && self.usage.map_or(true, |v| v == usage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn matches_test() {
+        let mut device = HidDevice::new(Uuid::new_v4(), "test".into());
+        device.vendor_id = 0x1234;
+        device.product_id = 0x5678;
+        device.usage_page = 0xff00;
+        device.usage = 0x01;
+        device.serial = "A1".into();
+
+        assert!(DeviceFilter::default().matches(&device));
+        assert!(DeviceFilter::vendor_defined().matches(&device));
+        assert!(DeviceFilter { vendor_id: Some(0x1234), usage_page: Some(0xff00), ..Default::default() }.matches(&device));
+        assert!(DeviceFilter { serial: Some("A1".into()), ..Default::default() }.matches(&device));
+        assert!(!DeviceFilter { vendor_id: Some(0x1234), product_id: Some(0x0001), ..Default::default() }.matches(&device));
+        assert!(!DeviceFilter { usage: Some(0x02), ..Default::default() }.matches(&device));
+        assert!(!DeviceFilter { serial: Some("B2".into()), ..Default::default() }.matches(&device));
+    }
+
+    #[test]
+    fn matches_manufacturer_prefix_test() {
+        let mut device = HidDevice::new(Uuid::new_v4(), "test".into());
+        let filter = DeviceFilter { manufacturer_prefix: Some("acme".into()), ..Default::default() };
+        // 未读取字符串
+        assert!(!filter.matches(&device));
+        // 读取字符串失败（设备不存在）时不匹配
+        assert!(!filter.matches_loading_strings(&mut device.clone()));
+        device.manufacturer = "ACME Corp.".into();
+        assert!(filter.matches(&device));
+        device.manufacturer = "Not ACME".into();
+        assert!(!filter.matches(&device));
+    }
+
+    #[test]
+    fn matches_manufacturer_prefix_enumerated_test() {
+        let mut device = crate::hid_device::all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        let manufacturer = device.strings().unwrap().manufacturer.unwrap();
+        // 枚举得到的设备没有字符串，matches 不读取，matches_loading_strings 读取后匹配
+        assert!(device.manufacturer.is_empty());
+        let filter = DeviceFilter { manufacturer_prefix: Some(manufacturer.chars().take(3).collect()), ..Default::default() };
+        assert!(!filter.matches(&device));
+        assert!(filter.matches_loading_strings(&mut device));
+        assert_eq!(device.manufacturer, manufacturer);
+        assert!(filter.matches(&device));
+    }
+
+    #[test]
+    fn matches_nested_collection_test() {
+        let mut device = HidDevice::new(Uuid::new_v4(), "test".into());
+        device.usage_page = 0x01;
+        device.usage = 0x06;
+        device.usages = vec![(0x01, 0x06), (0xff00, 0x01)];
+
+        assert!(DeviceFilter::vendor_defined().matches(&device));
+        assert!(DeviceFilter { usage_page: Some(0xff00), usage: Some(0x01), ..Default::default() }.matches(&device));
+        // usage_page 和 usage 需要来自同一个集合
+        assert!(!DeviceFilter { usage_page: Some(0xff00), usage: Some(0x06), ..Default::default() }.matches(&device));
+    }
+}
@@ -1,31 +1,60 @@
 use std::{
-    ffi::{OsString, c_void}, mem::size_of,
-    sync::{atomic::{AtomicBool, Ordering}, Arc, RwLock}
+    collections::{BTreeMap, HashSet}, ffi::{OsStr, OsString, c_void}, fmt::Write, mem::size_of, ops::Deref,
+    sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc, RwLock, Mutex, MutexGuard},
+    thread::{sleep, spawn}, time::{Duration, Instant},
 };
-use anyhow::{Result, bail};
+use crossbeam_channel::{bounded, unbounded, Receiver};
 use uuid::Uuid;
 use windows::{
+    core::PCWSTR,
     Win32::{
         Storage::FileSystem::{
-            CreateFileW, FILE_GENERIC_READ, FILE_GENERIC_WRITE, FILE_SHARE_READ, FILE_SHARE_WRITE, FILE_ATTRIBUTE_NORMAL, OPEN_EXISTING, WriteFile, ReadFile
+            CreateFileW, FILE_ACCESS_FLAGS, FILE_FLAGS_AND_ATTRIBUTES, FILE_GENERIC_READ, FILE_GENERIC_WRITE, FILE_SHARE_READ, FILE_SHARE_WRITE, FILE_ATTRIBUTE_NORMAL, FILE_FLAG_OVERLAPPED, OPEN_EXISTING, WriteFile, ReadFile
         },
+        Devices::DeviceAndDriverInstallation::SP_DEVINFO_DATA,
         Devices::HumanInterfaceDevice::{
-            HIDD_ATTRIBUTES,
-            HIDP_CAPS,
+            HIDD_ATTRIBUTES, HIDD_CONFIGURATION, HidD_GetConfiguration, HidD_SetConfiguration,
+            HIDP_CAPS, HIDP_BUTTON_CAPS, HIDP_VALUE_CAPS, HIDP_REPORT_TYPE, HidP_Input, HidP_Output, HidP_Feature,
+            HidP_GetButtonCaps, HidP_GetValueCaps, HidP_GetLinkCollectionNodes, HIDP_LINK_COLLECTION_NODE,
             HidD_GetHidGuid, 
             HidD_GetPreparsedData,
             HidP_GetCaps,
             HidD_FreePreparsedData, 
-            HidD_GetAttributes, HidD_SetOutputReport, HidD_GetInputReport, HidD_GetFeature, HidD_FlushQueue,
+            HidD_GetAttributes, HidD_GetManufacturerString, HidD_GetProductString, HidD_GetSerialNumberString,
+            HidD_SetOutputReport, HidD_GetInputReport, HidD_GetFeature, HidD_SetFeature, HidD_FlushQueue,
         },
         Foundation::{
-            HANDLE,
-            CloseHandle,
-        }
+            HANDLE, BOOLEAN,
+            CloseHandle, GetLastError, ERROR_IO_PENDING, ERROR_IO_INCOMPLETE, ERROR_OPERATION_ABORTED, ERROR_ACCESS_DENIED, ERROR_SHARING_VIOLATION, WAIT_TIMEOUT,
+            ERROR_INVALID_FUNCTION, ERROR_GEN_FAILURE, ERROR_NOT_SUPPORTED,
+        },
+        System::{
+            IO::{OVERLAPPED, GetOverlappedResult, GetOverlappedResultEx, CancelIoEx, DeviceIoControl},
+            Threading::{CreateEventW, ResetEvent, WAIT_IO_COMPLETION},
+        },
     }
 };
 
-use super::{Error,utils::to_uuid, device_interface::DeviceInfoSet};
+use super::{Error,Result,usage,device_path,write_queue::{self, WriteQueue},filter::DeviceFilter,utils::{to_uuid, container_path_uuid, path_hash}, device_interface::DeviceInfoSet, usb_device::{UsbSpeed, usb_port_info, usb_port_number, location_info}};
+
+/// 无限等待
+const INFINITE: u32 = 0xFFFFFFFF;
+
+/// OVERLAPPED.Internal 为该值时操作尚未完成
+const STATUS_PENDING: usize = 0x103;
+
+/// trace-io 日志中最多记录的报告字节数
+#[cfg(feature = "trace-io")]
+const TRACE_IO_BYTES: usize = 16;
+
+/// HID_OUT_CTL_CODE(105)，hid.dll 没有对应的 HidD_GetOutputReport
+const IOCTL_HID_GET_OUTPUT_REPORT: u32 = 0x000B01A6;
+
+/// HID_OUT_CTL_CODE(100)，HidD_GetFeature 使用的 IOCTL
+const IOCTL_HID_GET_FEATURE: u32 = 0x000B0192;
+
+/// HID_OUT_CTL_CODE(120)，HidD_GetIndexedString 使用的 IOCTL，输入的高 16 位为语言 ID
+const IOCTL_HID_GET_INDEXED_STRING: u32 = 0x000B01E2;
 
 /// 1.获取所有设备，获取想要的设备信息
 ///
@@ -41,9 +70,34 @@ use super::{Error,utils::to_uuid, device_interface::DeviceInfoSet};
 ///     d.read_file /*Interrupt*/
 ///     e.write_file /*Interrupt*/
 ///
+/// # 报告 ID
+///
+/// 各报告长度（input/output/feature_report_byte_length）都包含开头 1 个字节的报告 ID，
+/// 单个报告最多携带 长度 - 1 个字节的数据。设备不使用编号报告时（uses_report_ids 为 false）
+/// 报告 ID 传 0，发送的数据开头仍然是这个 0 字节，总长度仍为完整的报告长度，不能省略。
+/// 读取的方法会去掉开头的报告 ID，只返回数据部分，可以通过 set_strip_report_id 关闭。
+///
+/// # 线程安全
+///
+/// HidDevice 是 Send + Sync 的，clone 出的设备共享同一个句柄（Arc<DeviceHandle>），
+/// 任意一个调用 close_device 会关闭所有克隆的句柄；clone_independent 出的设备使用各自的句柄。
+/// 除 load_info 外的方法都只需要 &self，可以在多个线程中同时调用。
+/// read、write、get_*、set_* 等方法结束时会关闭共享的句柄，同一设备上的这类调用
+/// 由设备内部的锁串行执行（打开→传输→关闭），不同设备之间互不影响、可以并行。
+/// read、read_report、read_until 只在打开和关闭句柄时持有该锁，等待 input 报告期间其他线程可以写入，
+/// 读取结束前其他调用也不会关闭该句柄。
+/// read_continuous、has_pending_input 不会关闭句柄也不持有该锁，适合在单个读线程中循环调用，
+/// 但其他线程的 read、write 等调用结束时仍会关闭它们正在使用的句柄。
+/// 调用 open 之后，这些方法结束时不再关闭句柄，直到对应的 close；句柄在最后一个克隆销毁时关闭。
 #[derive(Debug,Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct HidDevice{
     pub id:Uuid,
+    pub container_id:Uuid,                                 // 所属物理设备的 container id，复合设备的各接口相同；未知时为 nil
+    pub usb_version:Option<u16>,                          // 设备描述符中的 bcdUSB，如 0x0200 表示 USB 2.0；枚举时不读取，见 load_usb_info
+    pub speed:Option<UsbSpeed>,                          // 设备当前的运行速度，可能低于 usb_version 支持的速度；枚举时不读取，见 load_usb_info
+    pub location:Option<String>,                         // 所在 USB 设备的位置信息，如 Port_#0004.Hub_#0003；非 USB 设备为 None
+    #[cfg_attr(feature = "serde", serde(with = "crate::utils::os_string_serde"))]
     pub path:OsString,                                       //< stores the device's path. std::string             
     pub serial:String,                                    //< stores the device's serial number. std::wstring            
     pub manufacturer:String,                             //< stores the device's manufacturer. std::wstring            
@@ -53,38 +107,235 @@ pub struct HidDevice{
     pub release:u16,                               //< stores the device's relase number. unsigned short          
     pub usage_page:u16,                                   //< stores the device's usage page. unsigned short          
     pub usage:u16,                                  //< stores the device's usage. unsigned short          
+    pub usages:Vec<(u16, u16)>,                     // 所有 application 集合的 (usage_page, usage)，包括顶层集合
      // interface_number:u16,                           //< stores the device's interface number. int                     
     pub input_report_byte_length:u32,                    // 指定所有输入报告的最大大小（以字节为单位）。包括报表数据前面的报表 ID。如果未使用报表 ID，则 ID 值为零。      
     pub output_report_byte_length:u32,                   //< stores the device's write buffer size. unsigned short          
     pub feature_report_byte_length:u32,                   //< stores the device's write buffer size. unsigned short 
+    numbered_reports:bool,                                // 是否使用编号报告，由 caps 中的 ReportID 得出
+    absent:bool,                                          // 系统记录过但当前未连接的设备，无法打开
     //  readFifoBuffer;                              // internal read fifo buffer. 
     // *backgroundReader;                            // backgroud reader system. HidDeviceReaderThread   *
+    #[cfg_attr(feature = "serde", serde(skip))]
+    open_options: OpenOptions,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    keep_report_id: bool,                                 // set_strip_report_id(false) 之后读取的数据保留开头的报告 ID
+    #[cfg_attr(feature = "serde", serde(skip))]
     device_handle: Arc<DeviceHandle>,
 }
 
+/// 打开设备的选项
+#[derive(Debug, Default, Clone)]
+pub struct OpenOptions {
+    /// 打开设备的超时时间，None 表示一直等待 CreateFileW 返回
+    pub timeout: Option<Duration>,
+    /// 传给 CreateFileW 的额外标志，如 FILE_FLAG_NO_BUFFERING
+    ///
+    /// 读写都使用 overlapped I/O，FILE_ATTRIBUTE_NORMAL | FILE_FLAG_OVERLAPPED 总会加上，默认不加其他标志
+    pub flags: FILE_FLAGS_AND_ATTRIBUTES,
+    /// 枚举时生成设备 id 的方式，只用于 all_hid_device_with_options，打开单个设备时不使用
+    pub id_scheme: IdScheme,
+}
+
+/// HidD_GetConfiguration 返回的配置（HIDD_CONFIGURATION），修改后传给 set_configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HidConfiguration {
+    /// 驱动为该设备缓冲的 input 报告个数
+    pub ring_buffer_size: u32,
+    cookie: usize,                  // 驱动校验用，原样传回
+}
+
+impl From<HIDD_CONFIGURATION> for HidConfiguration {
+    fn from(config: HIDD_CONFIGURATION) -> Self {
+        Self {
+            ring_buffer_size: config.RingBufferSize,
+            cookie: config.cookie as usize,
+        }
+    }
+}
+
+/// get_input_report_any、send_output_report 实际使用的传输方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transport {
+    /// HidD_GetInputReport / HidD_SetOutputReport，控制传输
+    Control,
+    /// ReadFile / WriteFile，中断传输
+    Interrupt,
+}
+
+/// 报告类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReportKind {
+    Input,
+    Output,
+    Feature,
+}
+
+impl ReportKind {
+    fn report_type(&self) -> HIDP_REPORT_TYPE {
+        match self {
+            ReportKind::Input => HidP_Input,
+            ReportKind::Output => HidP_Output,
+            ReportKind::Feature => HidP_Feature,
+        }
+    }
+}
+
+/// 报告描述符中的集合（link collection）节点
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkCollectionNode {
+    pub usage: u16,
+    pub usage_page: u16,
+    pub parent: u16,                // 父节点索引
+    pub number_of_children: u16,
+    pub next_sibling: u16,          // 下一个兄弟节点索引，0 表示没有
+    pub first_child: u16,           // 第一个子节点索引，0 表示没有
+    pub collection_type: u8,        // 0x00 Physical, 0x01 Application, 0x02 Logical ...
+    pub is_alias: bool,
+}
+
+/// 枚举设备时由 container id 生成设备 id 的方式，见 OpenOptions::id_scheme
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum IdScheme {
+    /// 直接使用 container id，同一物理设备的多个接口 id 相同（默认，与旧版本相同）
+    #[default]
+    ContainerId,
+    /// 以 container id 为命名空间、设备路径为名称生成 UUIDv5，每个接口的 id 都不同，适合复合设备
+    ContainerPath,
+}
+
+/// 设备的字符串描述符，设备没有提供的字符串为 None
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DeviceStrings {
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial: Option<String>,
+}
+
+/// 某个报告 ID 在各类报告中的字节长度（包含 1 字节报告 ID），没有该类报告时为 None
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReportLengths {
+    pub input: Option<usize>,
+    pub output: Option<usize>,
+    pub feature: Option<usize>,
+}
+
+/// 报告 ID 到各类报告长度的映射，见 HidDevice::report_map
+pub type ReportMap = BTreeMap<u8, ReportLengths>;
+
+// 编译期保证 HidDevice 可以在线程间传递和共享
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<HidDevice>();
+};
+
 #[derive(Debug,Default)]
 struct DeviceHandle {
     handle:RwLock<Option<HANDLE>>, // 打开该HID 设备的句柄 使用内部可变   
     opened:AtomicBool,             // stores the device file's status. mutable bool       使用内部可变     
+    pending:Mutex<Option<Vec<u8>>>, // has_pending_input 探测时读到、尚未被取走的 input 报告
+    read_only:AtomicBool,          // 写权限被拒绝，当前句柄只以读权限或 0 权限打开
+    io:Mutex<()>,                  // 会关闭句柄的操作持有该锁，避免互相关闭对方正在使用的句柄
+    readers:AtomicUsize,           // 不持有 io 锁、正在等待 input 报告的读取数，大于 0 时读写结束后不关闭句柄
+    read_event:Mutex<Option<HANDLE>>, // overlapped 读取复用的手动重置事件，第一次读取时创建
+    open_count:AtomicUsize,        // open 的次数，大于 0 时读写结束后不关闭句柄
+    preparsed:Mutex<Option<Arc<PreparsedData>>>, // 缓存的 preparsed data，与句柄无关，关闭设备后仍然有效
+    report_ids:Mutex<Option<Option<ReportIds>>>, // check_report_id 使用的报告 ID，内层为 None 表示获取 preparsed data 失败
+    #[cfg(feature = "stats")]
+    stats:IoCounters,              // stats feature 的读写计数，克隆的设备共享
+}
+
+/// HidDevice::stats 返回的读写统计，克隆出的设备共享同一份统计
+#[cfg(feature = "stats")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct HidStats {
+    /// read、get_* 读到的字节数，包括报告 ID
+    pub bytes_read: u64,
+    /// write、set_* 写入的字节数，包括报告 ID
+    pub bytes_written: u64,
+    pub reads: u64,
+    pub writes: u64,
+    /// 失败的传输次数，包括超时
+    pub errors: u64,
+}
+
+#[cfg(feature = "stats")]
+#[derive(Debug, Default)]
+struct IoCounters {
+    bytes_read: std::sync::atomic::AtomicU64,
+    bytes_written: std::sync::atomic::AtomicU64,
+    reads: std::sync::atomic::AtomicU64,
+    writes: std::sync::atomic::AtomicU64,
+    errors: std::sync::atomic::AtomicU64,
+}
+
+impl DeviceHandle {
+    /// 关闭缓存的读取事件
+    fn close_read_event(&self) {
+        if let Some(event) = self.read_event.lock().unwrap().take() {
+            unsafe { CloseHandle(event) };
+        }
+    }
+}
+
+/// 由 preparsed data 解析出的各类报告 ID，与 preparsed data 一起缓存
+#[derive(Debug, Default, Clone)]
+struct ReportIds {
+    input: Vec<u8>,
+    output: Vec<u8>,
+    feature: Vec<u8>,
 }
 
+impl ReportIds {
+    fn get(&self, kind:ReportKind) -> &[u8] {
+        match kind {
+            ReportKind::Input => &self.input,
+            ReportKind::Output => &self.output,
+            ReportKind::Feature => &self.feature,
+        }
+    }
+}
 
-/// 销毁时关闭该设备 
-impl Drop for HidDevice {
+/// 最后一个共享该句柄的设备销毁时关闭句柄
+impl Drop for DeviceHandle {
     fn drop(&mut self) {
-        let handle_read = match self.device_handle.handle.read() {
-            Ok(v) => v,
-            Err(_) => return,
-        };
-        
-        match *handle_read {
-            Some(handle) => {
-                if unsafe { !CloseHandle(handle) }.as_bool() {
-                    // println!("{}", Error::win32());
-                }
-            },
-            None => return,
+        if let Some(handle) = self.handle.get_mut().ok().and_then(|v| v.take()) {
+            unsafe { CloseHandle(handle) };
+        }
+        self.close_read_event();
+    }
+}
+
+
+/// HidD_GetPreparsedData 获取的 preparsed data，销毁时释放
+#[derive(Debug)]
+struct PreparsedData(isize);
+
+impl PreparsedData {
+    fn new(handle:HANDLE) -> Result<Self> {
+        let mut pp_data:isize = 0;
+        if unsafe { HidD_GetPreparsedData(handle,&mut pp_data) }.0 == 0 {
+            return Err(Error::win32());
         }
+        Ok(PreparsedData(pp_data))
+    }
+
+    /// HidP_GetCaps，失败时返回 InvalidPreparsedData
+    fn caps(&self) -> Result<HIDP_CAPS> {
+        let mut caps = HIDP_CAPS::default();
+        unsafe { HidP_GetCaps(self.0, &mut caps) }.map_err(|e| Error::InvalidPreparsedData(nt_status(&e)))?;
+        Ok(caps)
+    }
+}
+
+/// windows 将 HidP_* 返回的 NTSTATUS 转为 HRESULT 时加上了 FACILITY_NT_BIT，这里去掉该位
+fn nt_status(err:&windows::core::Error) -> u32 {
+    err.code().0 as u32 & !0x1000_0000
+}
+
+impl Drop for PreparsedData {
+    fn drop(&mut self) {
+        unsafe { HidD_FreePreparsedData(self.0) };
     }
 }
 
@@ -97,29 +348,233 @@ impl HidDevice {
         device
     }
 
+    /// 复制设备信息，但使用新的、未打开的句柄，与原设备互不影响
+    ///
+    /// clone 得到的设备共享同一个句柄，一个克隆的 close_device 或读写结束时会关闭其他克隆正在使用的句柄；
+    /// 需要在工作线程中单独管理句柄时使用 clone_independent
+    pub fn clone_independent(&self) -> HidDevice {
+        let mut device = self.clone();
+        device.device_handle = Arc::new(DeviceHandle::default());
+        device
+    }
+
+    /// 设置读取的方法是否去掉数据开头的报告 ID，默认去掉
+    ///
+    /// 影响 read、read_continuous、read_until、get_input_report、get_output_report、get_feature_report、
+    /// transact、ReportReader 等只返回数据的方法。设为 false 后返回的数据总是以报告 ID 开头，
+    /// 长度最多为 data_len + 1，适合自行处理报告 ID 的协议，避免数据恰好以报告 ID 的值开头时第一个字节被去掉。
+    /// 只影响当前设备，之后 clone 出的设备沿用该设置
+    pub fn set_strip_report_id(&mut self, strip:bool) {
+        self.keep_report_id = !strip;
+    }
+
+    /// 读取的方法是否去掉开头的报告 ID，见 set_strip_report_id
+    pub fn strips_report_id(&self) -> bool {
+        !self.keep_report_id
+    }
+
+    /// 打开设备时使用的选项
+    pub fn open_options(&self) -> &OpenOptions {
+        &self.open_options
+    }
+
+    /// 设置打开设备时使用的选项，下一次打开设备时生效
+    pub fn set_open_options(&mut self, options:OpenOptions) {
+        self.open_options = options;
+    }
+
+    /// 根据设备路径直接打开设备并获取设备信息，无需枚举所有设备
+    ///
+    /// 设备 id 与枚举时一致，按 IdScheme::default() 由 container id 得到
+    pub fn open_path(path:&OsStr) -> Result<Self> {
+        let (device_info_set, device_data) = DeviceInfoSet::for_path(path)?;
+        let mut device = interface_device(&device_info_set, path.to_os_string(), &device_data, IdScheme::default())?;
+        device.get_device_info()?;
+        Ok(device)
+    }
+
+    /// 由设备路径得到的 64 位 id，同一设备路径在不同进程、不同运行之间得到相同的值
+    ///
+    /// 路径不区分大小写。不同路径理论上可能得到相同的值，需要唯一性时使用 id 或 path
+    pub fn stable_id(&self) -> u64 {
+        path_hash(&self.path)
+    }
+
+    /// 设备 usage_page/usage 对应的名称，如 "Keyboard"、"Vendor-defined"
+    pub fn usage_label(&self) -> Option<&'static str> {
+        usage::usage_label(self.usage_page, self.usage)
+    }
+
+    /// 是否为 XInput 手柄（路径中带有 IG_），这类设备应优先通过 XInput 访问
+    pub fn is_xinput_device(&self) -> bool {
+        device_path::is_xinput_path(&self.path)
+    }
+
+    /// 设备当前是否已连接
+    ///
+    /// 只有 all_hid_device_including_absent 会返回未连接的设备，这类设备只有 id 和 path
+    pub fn is_present(&self) -> bool {
+        !self.absent
+    }
+
     /// 打开设备
     fn open_device(&self) -> Result<HANDLE> {
+        if self.absent {
+            return Err(Error::NotFound);
+        }
+        let (device_handle, read_only) = self.create_handle()?;
+        let mut handle_mut = self.device_handle.handle.write().unwrap();
+        *handle_mut = Some(device_handle.clone());
+        self.device_handle.read_only.store(read_only, Ordering::Relaxed);
+        self.device_handle.opened.store(true, Ordering::Relaxed);
+        Ok(device_handle)
+    }
+
+    /// 创建设备句柄，不保存到 device_handle 中
+    ///
+    /// 设置了打开超时时，在辅助线程中打开，超时后放弃等待并返回 OpenError，
+    /// 辅助线程之后拿到的句柄会被直接关闭
+    fn create_handle(&self) -> Result<(HANDLE, bool)> {
+        let flags = self.open_options.flags;
+        let timeout = match self.open_options.timeout {
+            Some(v) => v,
+            None => return Self::create_file(&self.path, flags),
+        };
+        let (sender, receiver) = bounded(1);
+        // 放弃等待的标记，辅助线程持有锁时发送，超时后不会再有句柄进入 channel
+        let abandoned = Arc::new(Mutex::new(false));
+        let thread_abandoned = abandoned.clone();
+        let path = self.path.clone();
+        spawn(move || {
+            let result = Self::create_file(&path, flags);
+            let abandoned = thread_abandoned.lock().unwrap();
+            if *abandoned {
+                if let Ok((handle, _)) = result {
+                    unsafe { CloseHandle(handle) };
+                }
+                return;
+            }
+            let _ = sender.send(result);
+        });
+        if let Ok(result) = receiver.recv_timeout(timeout) {
+            return result;
+        }
+        *abandoned.lock().unwrap() = true;
+        // 超时与辅助线程发送同时发生时，句柄已经在 channel 中
+        if let Ok(Ok((handle, _))) = receiver.try_recv() {
+            unsafe { CloseHandle(handle) };
+        }
+        Err(Error::OpenError)
+    }
+
+    /// 调用 CreateFileW 打开设备路径，返回句柄和是否只读
+    ///
+    /// 读写打开被拒绝（ERROR_ACCESS_DENIED）时退回只以读权限打开；
+    /// 系统独占的键盘、鼠标连读权限也会拒绝，此时与 hidapi 相同以 0 权限打开，
+    /// 只能获取属性、字符串、feature 报告等，不能读取 input 报告
+    fn create_file(path:&OsStr, flags:FILE_FLAGS_AND_ATTRIBUTES) -> Result<(HANDLE, bool)> {
+        let denied = |result:&Result<HANDLE>| matches!(result, Err(Error::Win32(code)) if *code == ERROR_ACCESS_DENIED.0);
+        let result = Self::create_file_access(path, FILE_GENERIC_READ | FILE_GENERIC_WRITE, flags);
+        if !denied(&result) {
+            return Ok((result?, false));
+        }
+        let result = Self::create_file_access(path, FILE_GENERIC_READ, flags);
+        if !denied(&result) {
+            return Ok((result?, true));
+        }
+        Ok((Self::create_file_access(path, FILE_ACCESS_FLAGS(0), flags)?, true))
+    }
+
+    /// 以指定的访问权限调用 CreateFileW，flags 之外总是加上 FILE_FLAG_OVERLAPPED
+    ///
+    /// ERROR_SHARING_VIOLATION 说明设备已被其他程序独占打开，返回 Error::Busy
+    fn create_file_access(path:&OsStr, access:FILE_ACCESS_FLAGS, flags:FILE_FLAGS_AND_ATTRIBUTES) -> Result<HANDLE> {
         unsafe {
             let device_handle  = CreateFileW(
-                self.path.clone(),
-                FILE_GENERIC_READ | FILE_GENERIC_WRITE, 
+                path,
+                access, 
                 FILE_SHARE_READ | FILE_SHARE_WRITE,
                 std::ptr::null(), 
                 OPEN_EXISTING, 
-                FILE_ATTRIBUTE_NORMAL, 
-                windows::Win32::Foundation::HANDLE::default())?;
+                FILE_ATTRIBUTE_NORMAL | FILE_FLAG_OVERLAPPED | flags, 
+                windows::Win32::Foundation::HANDLE::default()).map_err(|e| match Error::from(e) {
+                    Error::Win32(code) if code == ERROR_SHARING_VIOLATION.0 => Error::Busy,
+                    err => err,
+                })?;
             if device_handle.is_invalid(){
-                bail!(Error::OpenError);
+                return Err(Error::OpenError);
             }
-            let mut handle_mut = self.device_handle.handle.write().unwrap();
-            *handle_mut = Some(device_handle.clone());
-            self.device_handle.opened.store(true, Ordering::Relaxed);
             Ok(device_handle)
         }
     }
 
-    /// 关闭当前设备 
+    /// 尝试打开设备后立即关闭，判断设备当前是否可以进行读写
+    ///
+    /// 不影响已经打开的句柄
+    pub fn is_openable(&self) -> bool {
+        match self.create_handle() {
+            Ok((handle, _)) => {
+                unsafe { CloseHandle(handle) };
+                true
+            },
+            Err(_) => false,
+        }
+    }
+
+    /// 当前句柄是否以写权限（FILE_GENERIC_WRITE）打开
+    ///
+    /// 写权限被拒绝时句柄只以读权限（或 0 权限）打开，write、set_output_report 等返回 WriteNotPermitted。
+    /// 设备未打开时返回 false，需要先 open
+    pub fn can_write(&self) -> bool {
+        self.device_handle.handle.read().unwrap().is_some() && !self.device_handle.read_only.load(Ordering::Relaxed)
+    }
+
+    /// 打开设备并保持打开，直到调用相同次数的 close
+    ///
+    /// 默认每次读写结束后都会关闭设备；open 之后读写不再关闭句柄，
+    /// 可以连续读写而不必每次重新打开。可以嵌套调用，每次 open 对应一次 close
+    pub fn open(&self) -> Result<()> {
+        let _io = self.lock_io();
+        self.check_handle()?;
+        self.device_handle.open_count.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// 撤销一次 open，最后一次 close 时关闭设备，返回设备是否已被关闭
+    pub fn close(&self) -> bool {
+        let _io = self.lock_io();
+        if self.release_open() <= 1 {
+            return self.close_handle();
+        }
+        false
+    }
+
+    /// open 的次数减一（已经为 0 时不变），返回减之前的次数
+    fn release_open(&self) -> usize {
+        self.device_handle.open_count
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| count.checked_sub(1))
+            .unwrap_or(0)
+    }
+
+    /// 读写结束时调用，没有 open 且没有正在进行的读取时关闭设备
+    fn release_handle(&self) {
+        if self.device_handle.open_count.load(Ordering::Relaxed) == 0
+            && self.device_handle.readers.load(Ordering::Relaxed) == 0 {
+            self.close_handle();
+        }
+    }
+
+    /// 立即关闭当前设备，不论还有多少次 open；同时撤销一次 open（如果有）
+    ///
+    /// 其他 open 的使用者不受影响，之后的读写会重新打开设备并保持打开
     pub fn close_device(&self) -> bool {
+        let _io = self.lock_io();
+        self.release_open();
+        self.close_handle()
+    }
+
+    /// 关闭句柄，调用方需持有 io 锁，不改变 open 的次数
+    fn close_handle(&self) -> bool {
         match *self.device_handle.handle.read().unwrap() {
             Some(handle) => {
                 if unsafe { !CloseHandle(handle) }.as_bool() {
@@ -131,31 +586,253 @@ impl HidDevice {
         let mut handle_read = self.device_handle.handle.write().unwrap();
         *handle_read = None;
         self.device_handle.opened.store(false, Ordering::Relaxed);
+        self.device_handle.read_only.store(false, Ordering::Relaxed);
+        *self.device_handle.pending.lock().unwrap() = None;
+        // 句柄已关闭，正在进行的读取会结束并释放读取事件
+        self.device_handle.close_read_event();
         true
     }
 
+    /// 获取当前打开的设备句柄，未打开时先打开设备，用于调用本库未封装的 Win32 接口
+    ///
+    /// 句柄归 HidDevice 所有，调用方不能 CloseHandle；
+    /// 本库的读写方法完成后会关闭设备，之后该句柄失效，需要重新获取；先调用 open 可以保持句柄有效
+    pub fn raw_handle(&self) -> Result<HANDLE> {
+        let _io = self.lock_io();
+        let handle = self.check_handle()?;
+        if handle.is_invalid() || handle.0 == 0 {
+            return Err(Error::NotOpen);
+        }
+        Ok(handle)
+    }
+
     /// 获取设备报告描述符信息
     fn get_usage_info(&mut self) -> Result<()> {
         let handle = self.device_handle.handle.read().unwrap().
             ok_or(Error::NotOpen)?;
-        unsafe {
-            let mut pp_data:isize = 0;
-            let mut cpas = HIDP_CAPS::default();
-            if HidD_GetPreparsedData(handle,&mut pp_data).0 == 1{
-                if let Err(err) = HidP_GetCaps(pp_data,&mut cpas){
-                    bail!(err);
+        let pp_data = PreparsedData::new(handle)?;
+        let cpas = pp_data.caps()?;
+        self.numbered_reports = Self::has_numbered_reports(&pp_data);
+        // 集合信息只用于按 usage 匹配，解析失败时只匹配顶层集合，不影响设备的其他信息
+        self.usages = Self::parse_link_collections(&pp_data)
+            .unwrap_or_default()
+            .iter()
+            .filter(|x| x.collection_type == 0x01)
+            .map(|x| (x.usage_page, x.usage))
+            .collect();
+        self.usage_page = cpas.UsagePage;
+        self.usage = cpas.Usage;
+        self.input_report_byte_length = cpas.InputReportByteLength as u32;
+        self.output_report_byte_length = cpas.OutputReportByteLength as u32;
+        self.feature_report_byte_length = cpas.FeatureReportByteLength as u32;
+        Ok(())
+    }
+
+    /// 判断设备是否有指定 ID 的某类报告
+    ///
+    /// 设备不使用编号报告时报告 ID 都为 0，此时查询 0 返回 true
+    pub fn supports_report(&self, report_id:u8, kind:ReportKind) -> Result<bool> {
+        Ok(self.report_ids(kind)?.contains(&report_id))
+    }
+
+    /// 设备没有该 ID 的某类报告时返回 InvalidReportId，在打开设备、持有 io 锁之前调用
+    ///
+    /// 第一次调用时解析所有报告 ID 并缓存，之后不再解析 caps；无法解析 caps 时不检查，
+    /// 失败同样会缓存，由之后的传输返回错误
+    fn check_report_id(&self, kind:ReportKind, report_id:u8) -> Result<()> {
+        let mut cached = self.device_handle.report_ids.lock().unwrap();
+        let report_ids = cached.get_or_insert_with(|| self.all_report_ids().ok());
+        match report_ids.as_ref().map(|ids| ids.get(kind)) {
+            Some(ids) if !ids.is_empty() && !ids.contains(&report_id) => Err(Error::InvalidReportId(report_id)),
+            _ => Ok(()),
+        }
+    }
+
+    /// 解析三类报告的报告 ID
+    fn all_report_ids(&self) -> Result<ReportIds> {
+        Ok(ReportIds {
+            input: self.report_ids(ReportKind::Input)?,
+            output: self.report_ids(ReportKind::Output)?,
+            feature: self.report_ids(ReportKind::Feature)?,
+        })
+    }
+
+    /// 报告 ID 不属于 link_collections 中下标为 collection 的集合（或其子集合）时返回 InvalidReportId
+    ///
+    /// 只有常量字段、caps 中找不到的报告交给 check_report_id 检查；无法解析 caps 时不检查
+    fn check_collection_report(&self, kind:ReportKind, collection:u16, report_id:u8) -> Result<()> {
+        let parsed = self.with_preparsed_data(|pp_data| {
+            Ok((Self::parse_link_collections(pp_data)?, Self::parse_report_caps(pp_data, kind)?))
+        });
+        let (nodes, (button_caps, value_caps)) = match parsed {
+            Ok(v) => v,
+            Err(_) => return Ok(()),
+        };
+        if collection as usize >= nodes.len() {
+            return Err(Error::InvalidCollection(collection));
+        }
+        let links: Vec<u16> = button_caps.iter().filter(|x| x.ReportID == report_id).map(|x| x.LinkCollection)
+            .chain(value_caps.iter().filter(|x| x.ReportID == report_id).map(|x| x.LinkCollection))
+            .collect();
+        if links.is_empty() || links.iter().any(|link| collection_contains(&nodes, collection, *link)) {
+            Ok(())
+        } else {
+            Err(Error::InvalidReportId(report_id))
+        }
+    }
+
+    /// 从 button/value caps 中获取某类报告的所有报告 ID
+    fn report_ids(&self, kind:ReportKind) -> Result<Vec<u8>> {
+        let (button_caps, value_caps) = self.report_caps(kind)?;
+        let mut ids: Vec<u8> = button_caps.iter().map(|x| x.ReportID)
+            .chain(value_caps.iter().map(|x| x.ReportID))
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        // 只有常量字段的报告没有 caps，但仍然可以用 0 号报告收发
+        if ids.is_empty() && self.report_byte_length(kind) > 1 {
+            ids.push(0);
+        }
+        Ok(ids)
+    }
+
+    /// 某类报告的最大长度（包含报告 ID）
+    fn report_byte_length(&self, kind:ReportKind) -> u32 {
+        match kind {
+            ReportKind::Input => self.input_report_byte_length,
+            ReportKind::Output => self.output_report_byte_length,
+            ReportKind::Feature => self.feature_report_byte_length,
+        }
+    }
+
+    /// 获取设备所有报告 ID 及其在 input/output/feature 中的字节长度
+    ///
+    /// 长度由 caps 的 BitSize * ReportCount（按钮为每个 1 bit）累加得出，向上取整到字节并加上报告 ID，
+    /// 不超过该类报告的最大长度。caps 中不包含常量（padding）字段，长度可能小于设备实际的报告长度
+    pub fn report_map(&self) -> Result<ReportMap> {
+        let caps = self.with_preparsed_data(|pp_data| {
+            [ReportKind::Input, ReportKind::Output, ReportKind::Feature].iter()
+                .map(|kind| Ok((*kind, Self::parse_report_caps(pp_data, *kind)?)))
+                .collect::<Result<Vec<_>>>()
+        })?;
+        let mut map = ReportMap::new();
+        for (kind, (button_caps, value_caps)) in caps {
+            let max_len = self.report_byte_length(kind) as usize;
+            for (report_id, bits) in report_bits(&button_caps, &value_caps) {
+                let len = Some(((bits + 7) / 8 + 1).min(max_len));
+                let lengths = map.entry(report_id).or_insert_with(ReportLengths::default);
+                match kind {
+                    ReportKind::Input => lengths.input = len,
+                    ReportKind::Output => lengths.output = len,
+                    ReportKind::Feature => lengths.feature = len,
                 }
-                HidD_FreePreparsedData(pp_data);
-            } else {
-                bail!(Error::win32());
             }
-            self.usage_page = cpas.UsagePage;
-            self.usage = cpas.Usage;
-            self.input_report_byte_length = cpas.InputReportByteLength as u32;
-            self.output_report_byte_length = cpas.OutputReportByteLength as u32;
-            self.feature_report_byte_length = cpas.FeatureReportByteLength as u32;
         }
-        Ok(())
+        Ok(map)
+    }
+
+    /// 获取某类报告的 button caps 和 value caps
+    fn report_caps(&self, kind:ReportKind) -> Result<(Vec<HIDP_BUTTON_CAPS>, Vec<HIDP_VALUE_CAPS>)> {
+        self.with_preparsed_data(|pp_data| Self::parse_report_caps(pp_data, kind))
+    }
+
+    /// 获取报告描述符中所有的集合节点
+    ///
+    /// 返回值的下标即为 parent、first_child、next_sibling 所指的节点索引，0 号为顶层集合
+    pub fn link_collections(&self) -> Result<Vec<LinkCollectionNode>> {
+        self.with_preparsed_data(Self::parse_link_collections)
+    }
+
+    /// 获取 preparsed data 并交给 f 解析
+    fn with_preparsed_data<T>(&self, f: impl FnOnce(&PreparsedData) -> Result<T>) -> Result<T> {
+        f(self.preparsed_data()?.as_ref())
+    }
+
+    /// 缓存的 preparsed data，第一次调用时打开设备获取，之后不再打开设备
+    fn preparsed_data(&self) -> Result<Arc<PreparsedData>> {
+        let mut cached = self.device_handle.preparsed.lock().unwrap();
+        if let Some(pp_data) = cached.as_ref() {
+            return Ok(pp_data.clone());
+        }
+        let _io = self.lock_io();
+        let handle = self.check_handle()?;
+        // 获取失败时同样要关闭临时打开的句柄
+        let pp_data = PreparsedData::new(handle);
+        self.release_handle();
+        let pp_data = Arc::new(pp_data?);
+        *cached = Some(pp_data.clone());
+        Ok(pp_data)
+    }
+
+    /// 所有 feature 报告的报告 ID，从小到大排列
+    ///
+    /// 各报告的长度见 report_map
+    pub fn feature_report_ids(&self) -> Result<Vec<u8>> {
+        self.report_ids(ReportKind::Feature)
+    }
+
+    /// 从 preparsed data 中解析集合节点
+    fn parse_link_collections(pp_data:&PreparsedData) -> Result<Vec<LinkCollectionNode>> {
+        let cpas = pp_data.caps()?;
+        let mut nodes = vec![];
+        unsafe {
+            let mut len = cpas.NumberLinkCollectionNodes as u32;
+            nodes.resize(len as usize, HIDP_LINK_COLLECTION_NODE::default());
+            if len > 0 {
+                HidP_GetLinkCollectionNodes(nodes.as_mut_ptr(), &mut len, pp_data.0)?;
+                nodes.truncate(len as usize);
+            }
+        }
+        Ok(nodes.iter().map(|x| LinkCollectionNode {
+            usage: x.LinkUsage,
+            usage_page: x.LinkUsagePage,
+            parent: x.Parent,
+            number_of_children: x.NumberOfChildren,
+            next_sibling: x.NextSibling,
+            first_child: x.FirstChild,
+            collection_type: (x._bitfield & 0xFF) as u8,
+            is_alias: x._bitfield & 0x100 != 0,
+        }).collect())
+    }
+
+    /// 从 preparsed data 中解析某类报告的 caps
+    fn parse_report_caps(pp_data:&PreparsedData, kind:ReportKind) -> Result<(Vec<HIDP_BUTTON_CAPS>, Vec<HIDP_VALUE_CAPS>)> {
+        let cpas = pp_data.caps()?;
+        let (mut button_len, mut value_len) = match kind {
+            ReportKind::Input => (cpas.NumberInputButtonCaps, cpas.NumberInputValueCaps),
+            ReportKind::Output => (cpas.NumberOutputButtonCaps, cpas.NumberOutputValueCaps),
+            ReportKind::Feature => (cpas.NumberFeatureButtonCaps, cpas.NumberFeatureValueCaps),
+        };
+        let mut button_caps = vec![HIDP_BUTTON_CAPS::default(); button_len as usize];
+        if button_len > 0 {
+            unsafe { HidP_GetButtonCaps(kind.report_type(), button_caps.as_mut_ptr(), &mut button_len, pp_data.0)? };
+            button_caps.truncate(button_len as usize);
+        }
+        let mut value_caps = vec![HIDP_VALUE_CAPS::default(); value_len as usize];
+        if value_len > 0 {
+            unsafe { HidP_GetValueCaps(kind.report_type(), value_caps.as_mut_ptr(), &mut value_len, pp_data.0)? };
+            value_caps.truncate(value_len as usize);
+        }
+        Ok((button_caps, value_caps))
+    }
+
+    /// 设备是否使用编号报告（报告 ID 不为 0）
+    ///
+    /// 为 false 时收发数据的报告 ID 都应传 0
+    pub fn uses_report_ids(&self) -> bool {
+        self.numbered_reports
+    }
+
+    /// 任意一类报告的 caps 中存在非 0 的报告 ID，即为使用编号报告
+    fn has_numbered_reports(pp_data:&PreparsedData) -> bool {
+        [ReportKind::Input, ReportKind::Output, ReportKind::Feature].iter().any(|kind| {
+            match Self::parse_report_caps(pp_data, *kind) {
+                Ok((button_caps, value_caps)) => {
+                    button_caps.iter().any(|x| x.ReportID != 0) || value_caps.iter().any(|x| x.ReportID != 0)
+                },
+                Err(_) => false,
+            }
+        })
     }
 
     /// 获取设备属性
@@ -168,7 +845,7 @@ impl HidDevice {
                 ..std::mem::zeroed()
             };
             if HidD_GetAttributes(handle, &mut attributes).0 == 0 {
-                bail!(Error::win32());
+                return Err(Error::win32());
             }
             self.vendor_id = attributes.VendorID;
             self.product_id = attributes.ProductID;
@@ -177,134 +854,871 @@ impl HidDevice {
         Ok(())
     }
 
+    /// 生成包含设备所有已知信息的多行文本，用于提交问题时附上设备信息
+    ///
+    /// 包括路径、id、VID/PID、版本、USB 信息、字符串、usage、报告长度、各报告 ID 的长度和集合数。
+    /// 会打开设备读取字符串，设备无法打开时返回错误；报告描述符相关的部分解析失败时在对应行写出错误
+    pub fn diagnostics(&self) -> Result<String> {
+        let strings = self.strings()?;
+        let mut report = String::new();
+        let text = |v: &Option<String>| v.clone().unwrap_or_else(|| "-".into());
+        // 写入 String 不会失败
+        let _ = writeln!(report, "path: {}", self.path.to_string_lossy());
+        let _ = writeln!(report, "id: {}", self.id);
+        let _ = writeln!(report, "container id: {}", self.container_id);
+        let _ = writeln!(report, "vendor id: 0x{:04X}, product id: 0x{:04X}, release: 0x{:04X}", self.vendor_id, self.product_id, self.release);
+        // 没有调用过 load_usb_info 时在这里查询
+        let usb_info = match self.usb_version.zip(self.speed) {
+            Some(v) => Some(v),
+            None => DeviceInfoSet::for_path(&self.path).and_then(|(_, data)| usb_port_info(data.DevInst)).ok(),
+        };
+        let _ = writeln!(report, "usb version: {}, speed: {}, port: {}, location: {}",
+            usb_info.map_or("-".into(), |(v, _)| format!("0x{:04X}", v)),
+            usb_info.map_or("-".into(), |(_, v)| format!("{:?}", v)),
+            self.usb_port().map_or("-".into(), |v| v.to_string()),
+            text(&self.location));
+        let _ = writeln!(report, "manufacturer: {}", text(&strings.manufacturer));
+        let _ = writeln!(report, "product: {}", text(&strings.product));
+        let _ = writeln!(report, "serial: {}", text(&strings.serial));
+        let _ = writeln!(report, "usage page: 0x{:04X}, usage: 0x{:04X} ({})", self.usage_page, self.usage, self.usage_label().unwrap_or("unknown"));
+        let _ = writeln!(report, "application collections: {}", self.usages.iter()
+            .map(|(usage_page, usage)| format!("0x{:04X}/0x{:04X}", usage_page, usage))
+            .collect::<Vec<_>>()
+            .join(", "));
+        let _ = writeln!(report, "report lengths: input {}, output {}, feature {}, uses report ids: {}",
+            self.input_report_byte_length, self.output_report_byte_length, self.feature_report_byte_length, self.uses_report_ids());
+        match self.report_map() {
+            Ok(map) => {
+                let length = |v: Option<usize>| v.map_or("-".into(), |v| v.to_string());
+                for (report_id, lengths) in map {
+                    let _ = writeln!(report, "report 0x{:02X}: input {}, output {}, feature {}",
+                        report_id, length(lengths.input), length(lengths.output), length(lengths.feature));
+                }
+            },
+            Err(err) => { let _ = writeln!(report, "reports: {}", err); },
+        }
+        match self.link_collections() {
+            Ok(nodes) => { let _ = writeln!(report, "link collections: {}", nodes.len()); },
+            Err(err) => { let _ = writeln!(report, "link collections: {}", err); },
+        }
+        Ok(report)
+    }
+
+    /// 读取字符串描述符并保存到 manufacturer、product、serial，设备没有的字符串保存为空字符串
+    ///
+    /// 枚举设备时不读取字符串，需要按这些字段过滤（如 DeviceFilter::manufacturer_prefix）时先调用
+    pub fn load_strings(&mut self) -> Result<()> {
+        let strings = self.strings()?;
+        self.manufacturer = strings.manufacturer.unwrap_or_default();
+        self.product = strings.product.unwrap_or_default();
+        self.serial = strings.serial.unwrap_or_default();
+        Ok(())
+    }
+
+    /// 所在 USB 设备在 hub 上的端口号（DEVPKEY_Device_Address），与 location 中的 Port_#xxxx 相同
+    ///
+    /// 只读取设备属性，不打开设备或 hub；蓝牙等非 USB 设备返回 Error::NotFound
+    pub fn usb_port(&self) -> Result<u32> {
+        let (_, device_data) = DeviceInfoSet::for_path(&self.path)?;
+        usb_port_number(device_data.DevInst)
+    }
+
+    /// 向设备所在的 hub 查询 bcdUSB 和运行速度，保存到 usb_version、speed
+    ///
+    /// 需要打开 hub 并发送 IOCTL，枚举设备时不读取；蓝牙等非 USB 设备返回 Error::NotFound，字段保持 None
+    pub fn load_usb_info(&mut self) -> Result<()> {
+        let (_, device_data) = DeviceInfoSet::for_path(&self.path)?;
+        let (usb_version, speed) = usb_port_info(device_data.DevInst)?;
+        self.usb_version = Some(usb_version);
+        self.speed = Some(speed);
+        Ok(())
+    }
+
+    /// 获取设备信息（报告长度、usage、VID/PID 等），用于 iter_hid_devices 得到的设备
+    pub fn load_info(&mut self) -> Result<()> {
+        self.get_device_info()
+    }
+
+    /// 重新获取设备信息，返回报告长度、usage 等报告描述符相关的信息是否发生变化
+    ///
+    /// 用于固件升级后设备重新连接、报告描述符可能已经改变的场景。
+    /// 会先关闭当前句柄（包括 open 保持的句柄），之后的读写重新打开设备，open 的次数不变
+    pub fn refresh_info(&mut self) -> Result<bool> {
+        let before = self.capabilities();
+        {
+            let _io = self.lock_io();
+            self.close_handle();
+        }
+        *self.device_handle.preparsed.lock().unwrap() = None;
+        *self.device_handle.report_ids.lock().unwrap() = None;
+        self.get_device_info()?;
+        Ok(self.capabilities() != before)
+    }
+
+    /// 报告描述符决定的设备信息，用于 refresh_info 比较
+    fn capabilities(&self) -> (u16, u16, Vec<(u16, u16)>, u32, u32, u32, bool) {
+        (
+            self.usage_page,
+            self.usage,
+            self.usages.clone(),
+            self.input_report_byte_length,
+            self.output_report_byte_length,
+            self.feature_report_byte_length,
+            self.numbered_reports,
+        )
+    }
+
+    /// 打开一次设备，读取厂商、产品、序列号字符串
+    pub fn strings(&self) -> Result<DeviceStrings> {
+        let _io = self.lock_io();
+        let handle = self.check_handle()?;
+        let strings = unsafe {
+            DeviceStrings {
+                manufacturer: read_hid_string(|buffer, len| HidD_GetManufacturerString(handle, buffer, len)),
+                product: read_hid_string(|buffer, len| HidD_GetProductString(handle, buffer, len)),
+                serial: read_hid_string(|buffer, len| HidD_GetSerialNumberString(handle, buffer, len)),
+            }
+        };
+        self.release_handle();
+        Ok(strings)
+    }
+
+    /// 按语言读取字符串描述符，index 为 USB 字符串描述符序号，为空时返回 None
+    ///
+    /// strings、manufacturer 等只能读到默认语言的字符串。language_id 随请求传给 HID 类驱动，
+    /// 部分系统版本的驱动会忽略它而返回默认语言；厂商、产品、序列号字符串的序号由设备描述符决定，
+    /// 通常为 1、2、3，hid 接口无法直接查询
+    pub fn indexed_string(&self, index:u8, language_id:u16) -> Result<Option<String>> {
+        let units = self.indexed_string_units(index, language_id)?;
+        if units.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(String::from_utf16_lossy(&units)))
+    }
+
+    /// 设备支持的语言 ID（LANGID），即 0 号字符串描述符的内容
+    pub fn language_ids(&self) -> Result<Vec<u16>> {
+        self.indexed_string_units(0, 0)
+    }
+
+    /// 通过 IOCTL_HID_GET_INDEXED_STRING 读取字符串描述符，返回结尾 0 之前的 UTF-16 数据
+    fn indexed_string_units(&self, index:u8, language_id:u16) -> Result<Vec<u16>> {
+        let request = (index as u32 | ((language_id as u32) << 16)).to_le_bytes();
+        // USB 字符串描述符最多 126 个 UTF-16 字符，留出结尾的 0
+        let mut buffer = [0u8; 128 * size_of::<u16>()];
+        let _io = self.lock_io();
+        let handle = self.check_handle()?;
+        if self.io_control_overlapped(handle, IOCTL_HID_GET_INDEXED_STRING, &request, &mut buffer, INFINITE)?.is_none() {
+            return Err(Error::Other("get indexed string error".into()));
+        }
+        self.release_handle();
+        Ok(buffer.chunks_exact(2)
+            .map(|x| u16::from_le_bytes([x[0], x[1]]))
+            .take_while(|&x| x != 0)
+            .collect())
+    }
+
+    /// 重新读取 VID/PID/版本号，用于固件升级（DFU）后版本号发生变化的设备
+    pub fn refresh_attributes(&mut self) -> Result<()> {
+        let device_handle = self.device_handle.clone();
+        let _io = device_handle.io.lock().unwrap();
+        self.check_handle()?;
+        self.get_attributes_info().map_err(|e| {self.release_handle(); return e})?;
+        self.release_handle();
+        Ok(())
+    }
+
     /// 获取设备所有信息 
     fn get_device_info(&mut self) -> Result<()> {
+        let device_handle = self.device_handle.clone();
+        let _io = device_handle.io.lock().unwrap();
         self.open_device()?;
-        self.get_usage_info().map_err(|e| {self.close_device(); return e})?;
-        self.get_attributes_info().map_err(|e| {self.close_device(); return e})?;
-        self.close_device();
+        self.get_usage_info().map_err(|e| {self.release_handle(); return e})?;
+        self.get_attributes_info().map_err(|e| {self.release_handle(); return e})?;
+        self.release_handle();
         Ok(())
     }
 
     /// 设置output数据 
     pub fn set_output_report(&self,report_id:u8, data:&[u8]) -> Result<()>{
         if (data.len() + 1) as u32 > self.output_report_byte_length{
-            bail!(Error::DataOverlength);
+            return Err(Error::DataOverlength);
         }
-        let handle = self.check_handle()?;
+        self.check_report_id(ReportKind::Output, report_id)?;
+        let _io = self.lock_io();
+        let handle = self.check_write_handle()?;
         let send_data = self.output_assemble_data(report_id, data,self.output_report_byte_length as usize)?;
         if unsafe{HidD_SetOutputReport(handle, send_data.as_ptr() as *const c_void,self.output_report_byte_length)}.0 == 0 {
-            bail!(Error::win32());
+            return Err(self.io_error(Error::win32()));
         }
-        self.close_device();
+        self.record_io("set_output", &send_data);
+        self.release_handle();
         Ok(())
     }
 
+    /// 同 set_output_report，发送前确认报告属于 link_collections 中下标为 collection 的集合
+    ///
+    /// 同一个设备路径下报告 ID 不会重复，系统按报告 ID 找到对应的集合；多个集合的设备上，
+    /// 报告不在 collection 及其子集合中时返回 InvalidReportId，不会发给其他集合。
+    /// 只有一个集合的设备传 0，与 set_output_report 相同
+    pub fn set_output_report_in(&self,collection:u16, report_id:u8, data:&[u8]) -> Result<()>{
+        self.check_collection_report(ReportKind::Output, collection, report_id)?;
+        self.set_output_report(report_id, data)
+    }
+
+    /// 先尝试 set_output_report，设备不接受控制传输时改为通过 write 发送，返回实际使用的传输方式
+    pub fn send_output_report(&self,report_id:u8, data:&[u8]) -> Result<Transport>{
+        match self.set_output_report(report_id, data) {
+            Err(Error::Win32(code)) if is_unsupported_transfer(code) => {},
+            result => return result.map(|_| Transport::Control),
+        }
+        self.write(report_id, data)?;
+        Ok(Transport::Interrupt)
+    }
 
     /// 获取input数据 
     pub fn get_input_report(&self,report_id:u8, data_len:usize) -> Result<Vec<u8>>{
         if (data_len + 1)as u32 > self.input_report_byte_length{
-            bail!(Error::DataOverlength);
+            return Err(Error::DataOverlength);
         }
+        self.check_report_id(ReportKind::Input, report_id)?;
+        let _io = self.lock_io();
         let handle = self.check_handle()?;
         let mut send_data = self.input_assemble_data(report_id, self.input_report_byte_length as usize)?;
         if unsafe{HidD_GetInputReport(handle, send_data.as_mut_ptr() as *mut c_void,self.input_report_byte_length)}.0 == 0 {
-            bail!(Error::win32());
+            return Err(self.io_error(Error::win32()));
         }
-        self.close_device();
-        if send_data[0] == report_id{
-            send_data.remove(0);
-        } 
-        send_data.truncate(data_len);
-        Ok(send_data)
+        self.record_io("get_input", &send_data);
+        self.release_handle();
+        Ok(self.strip_report_id(report_id, send_data, data_len))
+    }
+
+    /// 先尝试 get_input_report，设备不支持控制传输读取 input 报告时改为读取一次中断报告
+    ///
+    /// 返回实际使用的传输方式。中断读取会一直等待设备发送下一个 input 报告，
+    /// 读到的报告 ID 可能与 report_id 不同
+    pub fn get_input_report_any(&self,report_id:u8, data_len:usize) -> Result<(Transport, Vec<u8>)>{
+        match self.get_input_report(report_id, data_len) {
+            Err(Error::Win32(code)) if is_unsupported_transfer(code) => {},
+            result => return result.map(|data| (Transport::Control, data)),
+        }
+        let read_data = self.with_read_handle(|handle| self.next_input_report(handle, report_id))?;
+        Ok((Transport::Interrupt, self.strip_report_id(report_id, read_data, data_len)))
+    }
+
+    /// 读回当前的 output 数据，用于确认写入的配置已经生效
+    ///
+    /// hid.dll 没有 HidD_GetOutputReport，直接发送 IOCTL_HID_GET_OUTPUT_REPORT，
+    /// 需要设备支持回读 output 报告
+    pub fn get_output_report(&self,report_id:u8, data_len:usize) -> Result<Vec<u8>>{
+        if (data_len + 1) as u32 > self.output_report_byte_length{
+            return Err(Error::DataOverlength);
+        }
+        self.check_report_id(ReportKind::Output, report_id)?;
+        let _io = self.lock_io();
+        let handle = self.check_handle()?;
+        let mut send_data = self.input_assemble_data(report_id, self.output_report_byte_length as usize)?;
+        if self.io_control_overlapped(handle, IOCTL_HID_GET_OUTPUT_REPORT, &[], &mut send_data, INFINITE)?.is_none() {
+            return Err(self.io_error(Error::Other("get output report error".into())));
+        }
+        self.record_io("get_output", &send_data);
+        self.release_handle();
+        Ok(self.strip_report_id(report_id, send_data, data_len))
     }
 
     /// 获取 feature数据 
     pub fn get_feature_report(&self,report_id:u8,data_len:usize) -> Result<Vec<u8>>{
         if (data_len + 1) as u32 > self.feature_report_byte_length{
-            bail!(Error::DataOverlength);
+            return Err(Error::DataOverlength);
         }
+        self.check_report_id(ReportKind::Feature, report_id)?;
+        let _io = self.lock_io();
         let handle = self.check_handle()?;
         let mut send_data = self.input_assemble_data(report_id, self.feature_report_byte_length as usize)?;
         if unsafe{HidD_GetFeature(handle, send_data.as_mut_ptr() as *mut c_void,self.feature_report_byte_length)}.0 == 0 {
-            bail!(Error::win32());
+            return Err(self.io_error(Error::win32()));
         }
-        self.close_device();
-        if send_data[0] == report_id{
-            send_data.remove(0);
-        }
-        send_data.truncate(data_len);
-        Ok(send_data)
+        self.record_io("get_feature", &send_data);
+        self.release_handle();
+        Ok(self.strip_report_id(report_id, send_data, data_len))
     }
 
-    /// 写入，可以异步
-    pub fn write(&self,report_id:u8, data:&[u8]) -> Result<u32>{
-        if (data.len() + 1) as u32 > self.output_report_byte_length {
-            bail!(Error::DataOverlength);
+    /// 同 get_feature_report，timeout 内未完成则通过 CancelIoEx 取消并返回 Ok(None)
+    ///
+    /// HidD_GetFeature 是同步调用，设备不响应时会一直阻塞；这里通过 overlapped 的 IOCTL_HID_GET_FEATURE 读取
+    pub fn get_feature_report_timeout(&self,report_id:u8, data_len:usize, timeout:Duration) -> Result<Option<Vec<u8>>>{
+        if (data_len + 1) as u32 > self.feature_report_byte_length{
+            return Err(Error::DataOverlength);
         }
-        let mut write_len:u32 = 0;
+        self.check_report_id(ReportKind::Feature, report_id)?;
+        let timeout = timeout_millis(timeout);
+        let _io = self.lock_io();
         let handle = self.check_handle()?;
-        let send_data = self.output_assemble_data(report_id, data, self.output_report_byte_length as usize)?;
-        if !unsafe{WriteFile(handle, send_data.as_ptr() as *const c_void,self.output_report_byte_length,&mut write_len,std::ptr::null_mut())}.as_bool(){
-            bail!(Error::win32());
-        }
-        self.close_device();
-        if write_len <= 0 {
-            bail!("write error");
+        let mut send_data = self.input_assemble_data(report_id, self.feature_report_byte_length as usize)?;
+        // 超时后 io_control_overlapped 会取消 IOCTL 并等待其结束，send_data 才能安全释放
+        let result = self.io_control_overlapped(handle, IOCTL_HID_GET_FEATURE, &[], &mut send_data, timeout);
+        self.release_handle();
+        if result?.is_none() {
+            return Ok(None);
         }
-        Ok(write_len)
+        self.record_io("get_feature", &send_data);
+        Ok(Some(self.strip_report_id(report_id, send_data, data_len)))
     }
 
-    /// 读取
-    pub fn read(&self,report_id:u8, data_len:usize) -> Result<Vec<u8>>{
-        // self.read_flush()?;
-        let read_data= self.read_continuous(report_id, data_len)?;
-        self.close_device();
-        Ok(read_data)
-    }
-
-    /// 刷新读缓冲区
-    pub fn read_flush(&self) -> Result<()>{
+    /// 读取完整的 feature 报告，返回 (实际报告ID, 数据)，数据长度为 feature_report_byte_length - 1
+    pub fn read_feature(&self,report_id:u8) -> Result<(u8,Vec<u8>)>{
+        if self.feature_report_byte_length < 1 {
+            return Err(Error::DataOverlength);
+        }
+        self.check_report_id(ReportKind::Feature, report_id)?;
+        let _io = self.lock_io();
+        let handle = self.check_handle()?;
+        let mut send_data = self.input_assemble_data(report_id, self.feature_report_byte_length as usize)?;
+        if unsafe{HidD_GetFeature(handle, send_data.as_mut_ptr() as *mut c_void,self.feature_report_byte_length)}.0 == 0 {
+            return Err(self.io_error(Error::win32()));
+        }
+        self.record_io("get_feature", &send_data);
+        self.release_handle();
+        let actual_id = send_data.remove(0);
+        Ok((actual_id,send_data))
+    }
+
+    /// 获取完整的 feature 报告，长度为 feature_report_byte_length，第 0 字节是设备返回的实际报告 ID
+    ///
+    /// 不需要预先知道数据长度；需要分开报告 ID 和数据时使用 read_feature
+    pub fn get_feature_full(&self,report_id:u8) -> Result<Vec<u8>>{
+        let (actual_id, mut data) = self.read_feature(report_id)?;
+        data.insert(0, actual_id);
+        Ok(data)
+    }
+
+    /// 设置 feature 数据，不足 feature_report_byte_length 的部分用 0 填充
+    pub fn set_feature_report(&self,report_id:u8, data:&[u8]) -> Result<()>{
+        if (data.len() + 1) as u32 > self.feature_report_byte_length{
+            return Err(Error::DataOverlength);
+        }
+        self.check_report_id(ReportKind::Feature, report_id)?;
+        let _io = self.lock_io();
+        let handle = self.check_write_handle()?;
+        let send_data = self.output_assemble_data(report_id, data,self.feature_report_byte_length as usize)?;
+        if unsafe{HidD_SetFeature(handle, send_data.as_ptr() as *const c_void,self.feature_report_byte_length)}.0 == 0 {
+            return Err(self.io_error(Error::win32()));
+        }
+        self.record_io("set_feature", &send_data);
+        self.release_handle();
+        Ok(())
+    }
+
+    /// 以固定间隔轮询 feature 报告，每次读到的数据都通过 channel 发送
+    ///
+    /// 后台线程在接收端被 drop 或读取出错时退出，出错时先发送该错误，之后 channel 断开
+    pub fn subscribe_feature(&self,report_id:u8, interval:Duration) -> Result<Receiver<Result<Vec<u8>>>>{
+        if self.feature_report_byte_length < 1 {
+            return Err(Error::DataOverlength);
+        }
+        let (sender, receiver) = unbounded();
+        let device = self.clone();
+        spawn(move || {
+            loop {
+                let data = match device.read_feature(report_id) {
+                    Ok((_, data)) => data,
+                    Err(err) => {
+                        let _ = sender.send(Err(err));
+                        break;
+                    }
+                };
+                if sender.send(Ok(data)).is_err() {
+                    break;
+                }
+                sleep(interval);
+            }
+        });
+        Ok(receiver)
+    }
+
+    /// 持续读取 input 报告，只在报告与上一个不同时通过 channel 发送，第一个报告总会发送
+    ///
+    /// 后台线程通过 ReportReader 读取，期间设备保持打开，只在报告变化时分配新的 Vec。
+    /// 发送的数据同 read，去掉了报告 ID。读取出错时发送该错误后线程退出，channel 随之断开；
+    /// 返回值被 drop 后，线程在读到下一个报告时退出（无论报告是否变化）
+    pub fn subscribe_changes(&self,report_id:u8) -> Result<ReportSubscription>{
+        if self.input_report_byte_length < 1 {
+            return Err(Error::DataOverlength);
+        }
+        let data_len = self.input_report_byte_length as usize - 1;
+        let mut reader = self.report_reader()?;
+        let (sender, receiver) = unbounded();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread_stopped = stopped.clone();
+        spawn(move || {
+            let mut last: Option<Vec<u8>> = None;
+            while !thread_stopped.load(Ordering::Relaxed) {
+                let data = match reader.read(report_id, data_len) {
+                    Ok(data) => data,
+                    Err(err) => {
+                        let _ = sender.send(Err(err));
+                        break;
+                    }
+                };
+                if last.as_deref() == Some(data) {
+                    continue;
+                }
+                let data = data.to_vec();
+                last = Some(data.clone());
+                if sender.send(Ok(data)).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(ReportSubscription { receiver, stopped })
+    }
+
+    /// 创建顺序写入队列，报告由后台线程逐个 write，调用方不必等待写入完成，见 WriteQueue
+    pub fn writer(&self) -> WriteQueue {
+        self.writer_with_timeout(write_queue::DEFAULT_WRITE_TIMEOUT)
+    }
+
+    /// 同 writer，每个报告的写入最多等待 timeout，超时后队列以 Error::Timeout 停止
+    pub fn writer_with_timeout(&self, timeout: Duration) -> WriteQueue {
+        WriteQueue::new(self.clone(), write_queue::DEFAULT_CAPACITY, timeout)
+    }
+
+    /// 写入，可以异步
+    pub fn write(&self,report_id:u8, data:&[u8]) -> Result<u32>{
+        self.write_report(report_id, data, self.output_report_byte_length as usize, INFINITE)
+    }
+
+    /// 同 write，发送前确认报告属于下标为 collection 的集合，见 set_output_report_in
+    pub fn write_in(&self,collection:u16, report_id:u8, data:&[u8]) -> Result<u32>{
+        self.check_collection_report(ReportKind::Output, collection, report_id)?;
+        self.write(report_id, data)
+    }
+
+    /// 写入，只发送 data.len() + 1 个字节，不用 0 填充到 output_report_byte_length
+    pub fn write_exact(&self,report_id:u8, data:&[u8]) -> Result<u32>{
+        self.write_report(report_id, data, data.len() + 1, INFINITE)
+    }
+
+    /// 写入，timeout 内未完成则取消写入并返回 Error::Timeout
+    ///
+    /// timeout 为 0 时只检查一次，写入未立即完成就取消并返回 Error::Timeout
+    pub fn write_timeout(&self,report_id:u8, data:&[u8], timeout:Duration) -> Result<u32>{
+        let timeout = timeout_millis(timeout);
+        self.write_report(report_id, data, self.output_report_byte_length as usize, timeout)
+    }
+
+    fn write_report(&self, report_id:u8, data:&[u8], len:usize, timeout:u32) -> Result<u32>{
+        if (data.len() + 1) as u32 > self.output_report_byte_length {
+            return Err(Error::DataOverlength);
+        }
+        self.check_report_id(ReportKind::Output, report_id)?;
+        let _io = self.lock_io();
+        let handle = self.check_write_handle()?;
+        let send_data = self.output_assemble_data(report_id, data, len)?;
+        let write_len = match self.write_overlapped(handle, &send_data, timeout)? {
+            Some(len) => len,
+            None if timeout != INFINITE => {
+                self.release_handle();
+                return Err(self.io_error(Error::Timeout));
+            }
+            None => 0,
+        };
+        self.record_io("write", &send_data[..(write_len as usize).min(send_data.len())]);
+        self.release_handle();
+        if write_len <= 0 {
+            return Err(self.io_error(Error::Other("write error".into())));
+        }
+        Ok(write_len)
+    }
+
+    /// 发送请求并读取响应，适合请求/响应式的厂商协议
+    ///
+    /// 通过 write 发送 request，再读取一个 input 报告，返回去掉报告 ID 后最多 response_len 个字节。
+    /// 发送前丢弃输入队列中已有的报告，避免把之前的报告当作响应。发送和读取期间持有设备锁，
+    /// 句柄在两者之间保持打开，其他线程的读写等 transact 完成后才执行。
+    /// timeout 包括发送和读取，期间没有收到报告时返回 Error::Timeout
+    pub fn transact(&self,report_id:u8, request:&[u8], response_len:usize, timeout:Duration) -> Result<Vec<u8>>{
+        if (request.len() + 1) as u32 > self.output_report_byte_length
+            || (response_len + 1) as u32 > self.input_report_byte_length {
+            return Err(Error::DataOverlength);
+        }
+        self.check_report_id(ReportKind::Output, report_id)?;
+        let deadline = Instant::now() + timeout;
+        let _io = self.lock_io();
+        let handle = self.check_write_handle()?;
+        let result = self.transact_with(handle, report_id, request, response_len, deadline);
+        self.release_handle();
+        result
+    }
+
+    fn transact_with(&self, handle:HANDLE, report_id:u8, request:&[u8], response_len:usize, deadline:Instant) -> Result<Vec<u8>>{
+        // 截止时间已过时也至少等待 1 毫秒，未完成的操作由 wait_overlapped 取消
+        let remaining = || deadline.saturating_duration_since(Instant::now()).as_millis().clamp(1, (INFINITE - 1) as u128) as u32;
+        *self.device_handle.pending.lock().unwrap() = None;
+        if unsafe { HidD_FlushQueue(handle) }.0 == 0 {
+            return Err(self.io_error(Error::win32()));
+        }
+        let send_data = self.output_assemble_data(report_id, request, self.output_report_byte_length as usize)?;
+        let write_len = match self.write_overlapped(handle, &send_data, remaining())? {
+            Some(len) => len,
+            None => return Err(self.io_error(Error::Timeout)),
+        };
+        self.record_io("write", &send_data[..(write_len as usize).min(send_data.len())]);
+        if write_len <= 0 {
+            return Err(self.io_error(Error::Other("write error".into())));
+        }
+        let mut read_data = self.input_assemble_data(report_id, self.input_report_byte_length as usize)?;
+        match self.read_overlapped(handle, &mut read_data, remaining())? {
+            Some(read_len) if read_len > 0 => {
+                let read_data = received_report(read_data, read_len);
+                self.record_io("read", &read_data);
+                Ok(self.strip_report_id(report_id, read_data, response_len))
+            },
+            Some(_) => Err(self.io_error(Error::Other("read error".into()))),
+            None => Err(self.io_error(Error::Timeout)),
+        }
+    }
+
+    /// 读取
+    pub fn read(&self,report_id:u8, data_len:usize) -> Result<Vec<u8>>{
+        // self.read_flush()?;
+        if (data_len + 1) as u32 > self.input_report_byte_length{
+            return Err(Error::DataOverlength);
+        }
+        let read_data = self.with_read_handle(|handle| self.next_input_report(handle, report_id))?;
+        Ok(self.strip_report_id(report_id, read_data, data_len))
+    }
+
+    /// HidD_GetConfiguration，获取驱动的输入环形缓冲区配置
+    pub fn get_configuration(&self) -> Result<HidConfiguration>{
+        let _io = self.lock_io();
+        let handle = self.check_handle()?;
+        let mut config = HIDD_CONFIGURATION { size: size_of::<HIDD_CONFIGURATION>() as u32, ..Default::default() };
+        let result = unsafe { HidD_GetConfiguration(handle, &mut config, size_of::<HIDD_CONFIGURATION>() as u32) };
+        let result = if result.0 == 0 { Err(Error::win32()) } else { Ok(HidConfiguration::from(config)) };
+        self.release_handle();
+        result
+    }
+
+    /// HidD_SetConfiguration，修改输入环形缓冲区的大小
+    ///
+    /// config 必须来自 get_configuration，驱动会校验其中的 cookie。
+    /// 不是所有驱动都支持，不支持时返回 Win32 错误；设置成功也不保证驱动按该值缓冲
+    pub fn set_configuration(&self, config:HidConfiguration) -> Result<()>{
+        let _io = self.lock_io();
+        let handle = self.check_handle()?;
+        let config = HIDD_CONFIGURATION {
+            cookie: config.cookie as *mut c_void,
+            size: size_of::<HIDD_CONFIGURATION>() as u32,
+            RingBufferSize: config.ring_buffer_size,
+        };
+        let result = unsafe { HidD_SetConfiguration(handle, &config, size_of::<HIDD_CONFIGURATION>() as u32) };
+        let result = if result.0 == 0 { Err(Error::win32()) } else { Ok(()) };
+        self.release_handle();
+        result
+    }
+
+    /// 刷新读缓冲区，has_pending_input 暂存的报告也一起丢弃
+    pub fn read_flush(&self) -> Result<()>{
         let handle = self.check_handle()?;
         if unsafe {HidD_FlushQueue(handle)}.0 == 0{
-            println!("Failed to flush the read buffer")
+            return Err(Error::win32());
         }
+        *self.device_handle.pending.lock().unwrap() = None;
         Ok(())
     }
 
+    /// 丢弃所有已排队的 input 报告，返回丢弃的报告数
+    ///
+    /// 逐个进行不等待的读取，直到没有可读的报告为止，不关闭设备
+    pub fn discard_input(&self) -> Result<usize>{
+        let mut pending = self.device_handle.pending.lock().unwrap();
+        let mut count = match pending.take() {
+            Some(_) => 1,
+            None => 0,
+        };
+        let handle = self.check_handle()?;
+        let mut read_data = vec![0u8; self.input_report_byte_length as usize];
+        while let Some(read_len) = self.read_overlapped(handle, &mut read_data, 0)? {
+            if read_len == 0 {
+                break;
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
     /// 读取 连续的
     pub fn read_continuous(&self,report_id:u8, data_len: usize) -> Result<Vec<u8>>{
         if (data_len + 1) as u32 > self.input_report_byte_length{
-            bail!(Error::DataOverlength);
+            return Err(Error::DataOverlength);
         }
-        let mut read_len:u32 = 0;
         let handle = self.check_handle()?;
-        let mut send_data = self.input_assemble_data(report_id, self.input_report_byte_length as usize)?;
-        if !unsafe{ReadFile(handle, send_data.as_mut_ptr() as *mut c_void,self.input_report_byte_length,&mut read_len,std::ptr::null_mut())}.as_bool(){
-            bail!(Error::win32());
+        let send_data = self.next_input_report(handle, report_id)?;
+        Ok(self.strip_report_id(report_id, send_data, data_len))
+    }
+
+    /// 创建复用缓冲区的读取器，适合读到报告后立即解析、不保留数据的循环读取，见 ReportReader
+    ///
+    /// 读取器存在期间设备保持打开（相当于 open），读取器 drop 时 close
+    pub fn report_reader(&self) -> Result<ReportReader> {
+        self.open()?;
+        Ok(ReportReader { device: self.clone(), buffer: Vec::with_capacity(self.input_report_byte_length as usize) })
+    }
+
+    /// 读取一个完整的 input 报告，返回 (实际报告ID, 数据)，数据长度最多为 input_report_byte_length - 1，
+    /// 设备发送的报告较短时为实际读到的长度
+    ///
+    /// 不需要预先知道数据长度
+    pub fn read_report(&self,report_id:u8) -> Result<(u8,Vec<u8>)>{
+        if self.input_report_byte_length < 1 {
+            return Err(Error::DataOverlength);
         }
+        let mut read_data = self.with_read_handle(|handle| self.next_input_report(handle, report_id))?;
+        let actual_id = read_data.remove(0);
+        Ok((actual_id,read_data))
+    }
+
+    /// 取出 has_pending_input 暂存的报告，没有时阻塞读取下一个 input 报告
+    fn next_input_report(&self, handle:HANDLE, report_id:u8) -> Result<Vec<u8>>{
+        let mut read_data = vec![];
+        self.next_input_report_into(handle, report_id, &mut read_data)?;
+        Ok(read_data)
+    }
+
+    /// 同 next_input_report，报告（包括报告 ID）读到 buffer 中，复用 buffer 已分配的空间
+    fn next_input_report_into(&self, handle:HANDLE, report_id:u8, buffer:&mut Vec<u8>) -> Result<()>{
+        let pending = self.device_handle.pending.lock().unwrap().take();
+        if let Some(v) = pending {
+            *buffer = v;
+            return Ok(());
+        }
+        buffer.clear();
+        buffer.resize(self.input_report_byte_length as usize, 0);
+        buffer[0] = report_id;
+        let read_len = self.read_overlapped(handle, buffer, INFINITE)?.unwrap_or(0);
         if read_len <= 0 {
-            bail!("read error");
+            return Err(self.io_error(Error::Other("read error".into())));
         }
-        if send_data[0] == report_id{
-            send_data.remove(0);
+        buffer.truncate(read_len as usize);
+        self.record_io("read", buffer);
+        Ok(())
+    }
+
+    /// 在 deadline 之前持续读取 input 报告，返回期间读到的所有报告
+    ///
+    /// 每次读取的超时时间为距 deadline 的剩余时间，deadline 到达时未完成的读取会被取消；读到 0 字节时同 read 返回错误
+    pub fn read_until(&self,report_id:u8, data_len:usize, deadline:Instant) -> Result<Vec<Vec<u8>>>{
+        if (data_len + 1) as u32 > self.input_report_byte_length{
+            return Err(Error::DataOverlength);
+        }
+        self.with_read_handle(|handle| {
+            let mut reports = vec![];
+            if let Some(v) = self.device_handle.pending.lock().unwrap().take() {
+                reports.push(self.strip_report_id(report_id, v, data_len));
+            }
+            loop {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                let timeout = timeout_millis(deadline - now);
+                let mut read_data = self.input_assemble_data(report_id, self.input_report_byte_length as usize)?;
+                match self.read_overlapped(handle, &mut read_data, timeout)? {
+                    Some(read_len) if read_len > 0 => {
+                        let read_data = received_report(read_data, read_len);
+                        self.record_io("read", &read_data);
+                        reports.push(self.strip_report_id(report_id, read_data, data_len));
+                    },
+                    // 同 read，读到 0 字节视为读取错误，继续读取只会空转
+                    Some(_) => return Err(self.io_error(Error::Other("read error".into()))),
+                    None => break,
+                }
+            }
+            Ok(reports)
+        })
+    }
+
+    /// 是否有尚未读取的 input 报告
+    ///
+    /// Windows 不提供输入队列中报告的准确数量，这里通过一次零超时的读取来探测；
+    /// 探测到的报告会被暂存，下一次 read_continuous 优先返回它，不会丢失。
+    /// 输入队列属于打开的句柄，关闭设备后队列及暂存的报告都会被丢弃。
+    pub fn has_pending_input(&self) -> Result<bool>{
+        let mut pending = self.device_handle.pending.lock().unwrap();
+        if pending.is_some() {
+            return Ok(true);
+        }
+        let handle = self.check_handle()?;
+        let mut read_data = vec![0u8; self.input_report_byte_length as usize];
+        match self.read_overlapped(handle, &mut read_data, 0)? {
+            Some(read_len) if read_len > 0 => {
+                let read_data = received_report(read_data, read_len);
+                self.record_io("read", &read_data);
+                *pending = Some(read_data);
+                Ok(true)
+            },
+            _ => Ok(false),
         }
-        send_data.truncate(data_len);
-        Ok(send_data)
+    }
+
+    /// overlapped 读取，timeout 毫秒内未完成则取消并返回 None
+    ///
+    /// 复用 DeviceHandle 中缓存的事件；其他线程正在使用该事件时临时创建一个，用完关闭
+    fn read_overlapped(&self, handle:HANDLE, buffer:&mut [u8], timeout:u32) -> Result<Option<u32>>{
+        let mut cached = self.device_handle.read_event.try_lock().ok();
+        unsafe {
+            let event = match cached.as_deref_mut() {
+                Some(slot) => {
+                    let event = match *slot {
+                        Some(event) => event,
+                        None => *slot.insert(CreateEventW(std::ptr::null(), true, false, PCWSTR::default())?),
+                    };
+                    ResetEvent(event);
+                    event
+                },
+                None => CreateEventW(std::ptr::null(), true, false, PCWSTR::default())?,
+            };
+            let mut overlapped = OVERLAPPED { hEvent: event, ..Default::default() };
+            let mut read_len:u32 = 0;
+            let result = if !ReadFile(handle, buffer.as_mut_ptr() as *mut c_void, buffer.len() as u32, &mut read_len, &mut overlapped).as_bool()
+                && GetLastError() != ERROR_IO_PENDING {
+                Err(Error::win32())
+            } else {
+                Self::wait_overlapped(handle, &overlapped, timeout)
+            };
+            if cached.is_none() {
+                CloseHandle(event);
+            }
+            result.map_err(|e| self.io_error(e))
+        }
+    }
+
+    /// overlapped 写入，timeout 毫秒内未完成则取消并返回 None
+    fn write_overlapped(&self, handle:HANDLE, buffer:&[u8], timeout:u32) -> Result<Option<u32>>{
+        unsafe {
+            let event = CreateEventW(std::ptr::null(), true, false, PCWSTR::default())?;
+            let mut overlapped = OVERLAPPED { hEvent: event, ..Default::default() };
+            let mut write_len:u32 = 0;
+            if !WriteFile(handle, buffer.as_ptr() as *const c_void, buffer.len() as u32, &mut write_len, &mut overlapped).as_bool()
+                && GetLastError() != ERROR_IO_PENDING {
+                let err = Error::win32();
+                CloseHandle(event);
+                return Err(self.io_error(err));
+            }
+            let result = Self::wait_overlapped(handle, &overlapped, timeout);
+            CloseHandle(event);
+            result.map_err(|e| self.io_error(e))
+        }
+    }
+
+    /// overlapped DeviceIoControl，timeout 毫秒内未完成则取消并返回 None
+    fn io_control_overlapped(&self, handle:HANDLE, code:u32, input:&[u8], output:&mut [u8], timeout:u32) -> Result<Option<u32>>{
+        unsafe {
+            let event = CreateEventW(std::ptr::null(), true, false, PCWSTR::default())?;
+            let mut overlapped = OVERLAPPED { hEvent: event, ..Default::default() };
+            let mut returned_len:u32 = 0;
+            if !DeviceIoControl(
+                handle,
+                code,
+                input.as_ptr() as *const c_void,
+                input.len() as u32,
+                output.as_mut_ptr() as *mut c_void,
+                output.len() as u32,
+                &mut returned_len,
+                &mut overlapped,
+            ).as_bool() && GetLastError() != ERROR_IO_PENDING {
+                let err = Error::win32();
+                CloseHandle(event);
+                return Err(self.io_error(err));
+            }
+            let result = Self::wait_overlapped(handle, &overlapped, timeout);
+            CloseHandle(event);
+            result.map_err(|e| self.io_error(e))
+        }
+    }
+
+    /// 等待 overlapped 操作完成，超时或被取消时返回 None
+    ///
+    /// 使用 GetOverlappedResultEx 的可警告等待：超时（timeout 毫秒，为 0 时只检查一次）或线程收到 APC 时
+    /// 取消该操作；被其他线程 CancelIoEx 取消时同样返回 None。
+    /// 返回时操作一定已经结束，overlapped 和缓冲区可以安全释放
+    unsafe fn wait_overlapped(handle:HANDLE, overlapped:&OVERLAPPED, timeout:u32) -> Result<Option<u32>>{
+        let mut transferred:u32 = 0;
+        if GetOverlappedResultEx(handle, overlapped, &mut transferred, timeout, true).as_bool() {
+            return Ok(Some(transferred));
+        }
+        let err = GetLastError();
+        if err == ERROR_OPERATION_ABORTED {
+            return Ok(None);
+        }
+        // timeout 为 0 时未完成的操作返回 ERROR_IO_INCOMPLETE；其他错误也先确认操作是否已经结束
+        let incomplete = err == WAIT_TIMEOUT || err == ERROR_IO_INCOMPLETE || err.0 == WAIT_IO_COMPLETION
+            || std::ptr::read_volatile(&overlapped.Internal) == STATUS_PENDING;
+        if !incomplete {
+            return Err(Error::Win32(err.0));
+        }
+        CancelIoEx(handle, overlapped);
+        // 取消后仍需等待操作真正结束，之后缓冲区才不会再被驱动写入
+        if !GetOverlappedResult(handle, overlapped, &mut transferred, true).as_bool() {
+            if GetLastError() == ERROR_OPERATION_ABORTED {
+                return Ok(None);
+            }
+            return Err(Error::win32());
+        }
+        // 取消之前操作已经完成
+        Ok(Some(transferred))
+    }
+
+    /// 直接向设备发送 IOCTL（如 IOCTL_HID_GET_REPORT_DESCRIPTOR），返回驱动写入 output 的字节数
+    ///
+    /// # Safety
+    ///
+    /// input/output 只在调用期间有效，驱动只会访问这两个缓冲区本身；
+    /// 对于 METHOD_NEITHER 或缓冲区中嵌入指针的 IOCTL，调用方需保证这些指针在调用期间有效，
+    /// 并且 code 对应的操作不会破坏设备状态。
+    pub unsafe fn device_io_control(&self, code:u32, input:&[u8], output:&mut [u8]) -> Result<usize>{
+        let _io = self.lock_io();
+        let handle = self.check_handle()?;
+        let returned_len = self.io_control_overlapped(handle, code, input, output, INFINITE)?.unwrap_or(0);
+        self.release_handle();
+        Ok(returned_len as usize)
     }
 
     /// 组装 output 数据
+    ///
+    /// 长度固定为 data_len（完整的报告长度），超过端点大小的报告由 HID 驱动负责拆包，
+    /// 这里必须一次提交完整报告，否则超出部分会被静默丢弃
     fn output_assemble_data(&self, report_id: u8, data: &[u8],data_len: usize) -> Result<Vec<u8>> {
-        let mut send_data: Vec<u8> = data.into_iter().map(|&x| x.clone()).collect();
-        send_data.reverse();
-        send_data.push(report_id);
-        send_data.reverse();
-        if send_data.len() < data_len{
-            send_data.append(&mut vec![0u8;data_len - send_data.len()])
+        if data.len() + 1 > data_len {
+            return Err(Error::DataOverlength);
         }
+        let mut send_data: Vec<u8> = Vec::with_capacity(data_len);
+        send_data.push(report_id);
+        send_data.extend_from_slice(data);
+        send_data.resize(data_len, 0);
         Ok(send_data)
     }
 
+    /// 报告 ID 与请求的一致时去掉开头的报告 ID，并截断到 data_len
+    ///
+    /// set_strip_report_id(false) 之后不去掉报告 ID，截断到报告 ID 加 data_len 个字节
+    fn strip_report_id(&self, report_id: u8, mut data: Vec<u8>, data_len: usize) -> Vec<u8> {
+        let (start, end) = self.report_data_range(report_id, &data, data_len);
+        data.truncate(end);
+        data.drain(..start);
+        data
+    }
+
+    /// strip_report_id 保留的数据在 data 中的范围 (start, end)
+    fn report_data_range(&self, report_id: u8, data: &[u8], data_len: usize) -> (usize, usize) {
+        if self.keep_report_id {
+            return (0, data.len().min(data_len + 1));
+        }
+        let start = if data[0] == report_id { 1 } else { 0 };
+        (start, data.len().min(start + data_len))
+    }
+
     /// 组装 input 数据
     fn input_assemble_data(&self, report_id: u8, data_len: usize) -> Result<Vec<u8>> {
         let mut read_data: Vec<u8> = vec![0; data_len];
@@ -312,6 +1726,65 @@ impl HidDevice {
         Ok(read_data)
     }
 
+    /// 每次传输完成后调用，记录 trace-io 日志和 stats 计数
+    ///
+    /// direction 为 read、get_* 时计为读取，其余计为写入
+    #[allow(unused_variables)]
+    fn record_io(&self, direction:&str, report:&[u8]) {
+        #[cfg(feature = "trace-io")]
+        self.trace_io(direction, report);
+        #[cfg(feature = "stats")]
+        {
+            let stats = &self.device_handle.stats;
+            let (count, bytes) = if direction == "read" || direction.starts_with("get_") {
+                (&stats.reads, &stats.bytes_read)
+            } else {
+                (&stats.writes, &stats.bytes_written)
+            };
+            count.fetch_add(1, Ordering::Relaxed);
+            bytes.fetch_add(report.len() as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// 传输失败时调用，计入 stats 的错误数，原样返回 err
+    fn io_error(&self, err:Error) -> Error {
+        #[cfg(feature = "stats")]
+        self.device_handle.stats.errors.fetch_add(1, Ordering::Relaxed);
+        err
+    }
+
+    /// 读写统计，需要开启 stats feature
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> HidStats {
+        let stats = &self.device_handle.stats;
+        HidStats {
+            bytes_read: stats.bytes_read.load(Ordering::Relaxed),
+            bytes_written: stats.bytes_written.load(Ordering::Relaxed),
+            reads: stats.reads.load(Ordering::Relaxed),
+            writes: stats.writes.load(Ordering::Relaxed),
+            errors: stats.errors.load(Ordering::Relaxed),
+        }
+    }
+
+    /// 开启 trace-io feature 时记录一次传输的方向、报告 ID、长度和前 TRACE_IO_BYTES 个字节
+    ///
+    /// report 为包含报告 ID 的完整报告
+    #[cfg(feature = "trace-io")]
+    fn trace_io(&self, direction:&str, report:&[u8]) {
+        tracing::trace!(
+            path = ?self.path,
+            direction,
+            report_id = report.first().copied().unwrap_or(0),
+            len = report.len(),
+            data = ?&report[..report.len().min(TRACE_IO_BYTES)],
+        );
+    }
+
+    /// 串行化同一设备（包括克隆）上 打开→传输→关闭 的完整操作
+    fn lock_io(&self) -> MutexGuard<'_, ()> {
+        self.device_handle.io.lock().unwrap()
+    }
+
     /// 检查设备句柄
     fn check_handle(&self) -> Result<HANDLE> {
         let read_handle = *self.device_handle.handle.read().unwrap();
@@ -321,33 +1794,411 @@ impl HidDevice {
         };
         Ok(handle)
     }
+
+    /// 只在打开和释放句柄时持有 io 锁，f 中的读取可能一直等待，期间不阻塞写入等其他操作
+    ///
+    /// f 执行期间计入 readers，其他操作结束时不会关闭该句柄
+    fn with_read_handle<T>(&self, f: impl FnOnce(HANDLE) -> Result<T>) -> Result<T> {
+        let handle = {
+            let _io = self.lock_io();
+            let handle = self.check_handle()?;
+            self.device_handle.readers.fetch_add(1, Ordering::Relaxed);
+            handle
+        };
+        let result = f(handle);
+        let _io = self.lock_io();
+        self.device_handle.readers.fetch_sub(1, Ordering::Relaxed);
+        self.release_handle();
+        result
+    }
+
+    /// 同 check_handle，句柄只以读权限打开时返回 WriteNotPermitted
+    fn check_write_handle(&self) -> Result<HANDLE> {
+        let handle = self.check_handle()?;
+        if self.device_handle.read_only.load(Ordering::Relaxed) {
+            return Err(Error::WriteNotPermitted);
+        }
+        Ok(handle)
+    }
+}
+
+/// 复用内部缓冲区读取 input 报告，由 HidDevice::report_reader 创建
+///
+/// read 返回指向内部缓冲区的切片，不为每个报告分配新的 Vec。切片借用了读取器（&mut self），
+/// 有效期到下一次 read 为止：持有切片时再次调用 read 或 drop 读取器都无法通过编译，
+/// 需要保留数据时 to_vec，或直接使用返回 Vec 的 HidDevice::read
+///
+/// ```no_run
+/// let device = usb_manager::hid_device::all_hid_device().unwrap().remove(0);
+/// let mut reader = device.report_reader().unwrap();
+/// loop {
+///     let data = reader.read(0x00, 64).unwrap();
+///     println!("{:?}", data);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ReportReader {
+    device: HidDevice,
+    buffer: Vec<u8>,
+}
+
+impl ReportReader {
+    /// 同 HidDevice::read_continuous，返回的数据去掉了报告 ID（见 HidDevice::set_strip_report_id），最多 data_len 个字节
+    pub fn read(&mut self, report_id:u8, data_len:usize) -> Result<&[u8]> {
+        if (data_len + 1) as u32 > self.device.input_report_byte_length{
+            return Err(Error::DataOverlength);
+        }
+        let handle = self.device.check_handle()?;
+        self.device.next_input_report_into(handle, report_id, &mut self.buffer)?;
+        let (start, end) = self.device.report_data_range(report_id, &self.buffer, data_len);
+        Ok(&self.buffer[start..end])
+    }
+
+    /// 读取使用的设备
+    pub fn device(&self) -> &HidDevice {
+        &self.device
+    }
+}
+
+impl Drop for ReportReader {
+    fn drop(&mut self) {
+        self.device.close();
+    }
+}
+
+/// HidDevice::subscribe_changes 返回的接收端，通过 Deref 当作 crossbeam Receiver 使用
+///
+/// drop 时通知后台线程退出，读取出错时收到该错误，之后 channel 断开
+#[derive(Debug)]
+pub struct ReportSubscription {
+    receiver: Receiver<Result<Vec<u8>>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Deref for ReportSubscription {
+    type Target = Receiver<Result<Vec<u8>>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.receiver
+    }
+}
+
+impl Drop for ReportSubscription {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+/// 下标为 node 的集合是否为 collection 或其子孙集合，nodes 为 link_collections 的结果
+fn collection_contains(nodes:&[LinkCollectionNode], collection:u16, mut node:u16) -> bool {
+    // 最多向上 nodes.len() 层，避免损坏的描述符中 parent 成环
+    for _ in 0..=nodes.len() {
+        if node == collection {
+            return true;
+        }
+        match nodes.get(node as usize) {
+            Some(v) if node != 0 => node = v.parent,
+            _ => return false,
+        }
+    }
+    false
+}
+
+/// 只保留 ReadFile 实际读到的 read_len 个字节，设备发送的报告可能短于 input_report_byte_length
+fn received_report(mut data:Vec<u8>, read_len:u32) -> Vec<u8> {
+    data.truncate(read_len as usize);
+    data
+}
+
+/// overlapped 等待使用的毫秒数，不足 1 毫秒的超时向上取整，避免变成只检查一次；不会达到 INFINITE
+fn timeout_millis(timeout:Duration) -> u32 {
+    if timeout.is_zero() { 0 } else { timeout.as_millis().clamp(1, (INFINITE - 1) as u128) as u32 }
+}
+
+/// 设备不支持该控制传输时 HidD_GetInputReport、HidD_SetOutputReport 返回的错误
+fn is_unsupported_transfer(code:u32) -> bool {
+    [ERROR_INVALID_FUNCTION, ERROR_GEN_FAILURE, ERROR_NOT_SUPPORTED].iter().any(|e| e.0 == code)
+}
+
+    /// 调用 HidD_Get*String 读取字符串，失败或为空时返回 None
+///
+/// USB 字符串描述符最多 126 个 UTF-16 字符，缓冲区留出结尾的 0
+unsafe fn read_hid_string(get: impl FnOnce(*mut c_void, u32) -> BOOLEAN) -> Option<String> {
+    let mut buffer = [0u16; 128];
+    if get(buffer.as_mut_ptr() as *mut c_void, (buffer.len() * size_of::<u16>()) as u32).0 == 0 {
+        return None;
+    }
+    let len = buffer.iter().position(|&x| x == 0).unwrap_or(buffer.len());
+    if len == 0 {
+        return None;
+    }
+    Some(String::from_utf16_lossy(&buffer[..len]))
+}
+
+/// 累加 caps 中每个报告 ID 的数据位数，跳过别名（IsAlias）caps
+fn report_bits(button_caps:&[HIDP_BUTTON_CAPS], value_caps:&[HIDP_VALUE_CAPS]) -> BTreeMap<u8, usize> {
+    let mut bits = BTreeMap::new();
+    for caps in button_caps.iter().filter(|x| x.IsAlias.0 == 0) {
+        let count = if caps.ReportCount > 0 {
+            caps.ReportCount as usize
+        } else if caps.IsRange.0 != 0 {
+            let range = unsafe { caps.Anonymous.Range };
+            range.UsageMax.saturating_sub(range.UsageMin) as usize + 1
+        } else {
+            1
+        };
+        *bits.entry(caps.ReportID).or_insert(0) += count;
+    }
+    for caps in value_caps.iter().filter(|x| x.IsAlias.0 == 0) {
+        *bits.entry(caps.ReportID).or_insert(0) += caps.BitSize as usize * caps.ReportCount as usize;
+    }
+    bits
+}
+
+/// 比较两次枚举的结果，按设备路径返回 (新增的设备, 移除的设备)
+///
+/// 适合自行定时调用 all_hid_device 的场景，不需要打开设备
+pub fn diff_devices(old:&[HidDevice], new:&[HidDevice]) -> (Vec<HidDevice>, Vec<HidDevice>) {
+    let old_paths = old.iter().map(|d| d.path.as_os_str()).collect::<HashSet<_>>();
+    let new_paths = new.iter().map(|d| d.path.as_os_str()).collect::<HashSet<_>>();
+    let added = new.iter().filter(|d| !old_paths.contains(d.path.as_os_str())).cloned().collect();
+    let removed = old.iter().filter(|d| !new_paths.contains(d.path.as_os_str())).cloned().collect();
+    (added, removed)
+}
+
+/// 获取所有的 hid 设备
+pub fn all_hid_device() -> Result<Vec<HidDevice>> {
+    all_hid_device_with_options(&OpenOptions::default())
+}
+
+/// 同 all_hid_device，枚举时打开各设备使用 options，如设置打开超时，超时的设备被跳过
+///
+/// options 只用于枚举，返回的设备使用默认的 OpenOptions
+pub fn all_hid_device_with_options(options:&OpenOptions) -> Result<Vec<HidDevice>> {
+    let mut list = vec![];
+    for device in enum_hid_devices(true, options.id_scheme)? {
+        let mut device_info = match device {
+            Ok(v) => v,
+            Err(_err) => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(error = %_err, "skip hid device without container id");
+                continue;
+            },
+        };
+        device_info.set_open_options(options.clone());
+        if let Err(_err) = device_info.get_device_info() {
+            continue;
+        }
+        device_info.set_open_options(OpenOptions::default());
+        list.push(device_info)
+    }
+    Ok(list)
+}
+
+/// 获取连接在指定 hub 端口上的 hid 设备，location 为 HidDevice::location 的值，如 `Port_#0004.Hub_#0003`
+///
+/// 不区分大小写。适合按物理位置（而不是序列号）定位被测设备的测试台，同一端口上复合设备的各接口都会返回
+pub fn all_hid_device_on_port(location:&str) -> Result<Vec<HidDevice>> {
+    Ok(all_hid_device()?.into_iter()
+        .filter(|device| device.location.as_deref().map_or(false, |v| v.eq_ignore_ascii_case(location)))
+        .collect())
+}
+
+/// 获取所有满足过滤条件的 hid 设备
+///
+/// filter 设置了 manufacturer_prefix 时读取设备的字符串，见 DeviceFilter::matches_loading_strings
+pub fn all_hid_device_matching(filter: &DeviceFilter) -> Result<Vec<HidDevice>> {
+    let mut list = all_hid_device()?;
+    list.retain_mut(|device| filter.matches_loading_strings(device));
+    Ok(list)
+}
+
+/// 获取所有的 hid 设备，按 (VID, PID, 接口号, 路径) 排序
+///
+/// all_hid_device 的顺序取决于 SetupAPI，每次可能不同；界面列表、测试等需要稳定顺序时使用
+pub fn all_hid_device_sorted() -> Result<Vec<HidDevice>> {
+    let mut list = all_hid_device()?;
+    sort_devices(&mut list);
+    Ok(list)
+}
+
+/// 按 (VID, PID, 接口号, 路径) 排序，没有接口号的设备排在同一 VID/PID 的最前面
+fn sort_devices(list:&mut [HidDevice]) {
+    list.sort_by_cached_key(|device| {
+        let interface_number = device_path::parse_device_path(&device.path).and_then(|ids| ids.interface_number);
+        (device.vendor_id, device.product_id, interface_number, device.path.clone())
+    });
+}
+
+/// 获取所有的 hid 设备，每个设备接口对应一项，打开失败的设备返回对应的错误
+///
+/// 枚举本身失败时只返回一项错误
+pub fn all_hid_device_verbose() -> Vec<Result<HidDevice>> {
+    iter_hid_devices().map(|device| {
+        let mut device_info = device?;
+        device_info.get_device_info()?;
+        Ok(device_info)
+    }).collect()
+}
+
+/// 惰性枚举 hid 设备，只填充 path 和 id，不打开设备
+///
+/// 调用方可以先根据 path 过滤，再对需要的设备调用 load_info 获取设备信息
+pub fn iter_hid_devices() -> impl Iterator<Item = Result<HidDevice>> {
+    let (devices, err) = match enum_hid_devices(true, IdScheme::default()) {
+        Ok(devices) => (Some(devices), None),
+        Err(err) => (None, Some(Err(err))),
+    };
+    devices.into_iter().flatten().chain(err)
+}
+
+/// hid_device_entries 枚举到的设备接口，只有不打开设备就能得到的信息，由 try_open 打开
+///
+/// 用于先列出所有接口、再按需打开的场景，如诊断界面中点击某个接口时才打开并显示失败原因
+#[derive(Debug, Clone)]
+pub struct HidDeviceEntry {
+    pub id: Uuid,
+    pub container_id: Uuid,
+    pub path: OsString,
+    /// 从路径解析的 VID/PID/接口号，路径格式无法识别时为 None
+    pub ids: Option<device_path::DeviceIds>,
+    device: HidDevice,
+}
+
+impl HidDeviceEntry {
+    /// 打开设备并获取设备信息，失败时返回具体的错误，如 Busy、Win32(ERROR_ACCESS_DENIED)
+    ///
+    /// 每次调用都重新打开，返回的设备使用各自的句柄
+    pub fn try_open(&self) -> Result<HidDevice> {
+        let mut device = self.device.clone_independent();
+        device.get_device_info()?;
+        Ok(device)
+    }
+}
+
+/// 惰性枚举 hid 设备接口，不打开设备，见 HidDeviceEntry
+///
+/// 基于 iter_hid_devices，无法获取 container id 的接口返回对应的错误，枚举本身失败时只返回一项错误
+pub fn hid_device_entries() -> impl Iterator<Item = Result<HidDeviceEntry>> {
+    iter_hid_devices().map(|device| {
+        let device = device?;
+        Ok(HidDeviceEntry {
+            id: device.id,
+            container_id: device.container_id,
+            path: device.path.clone(),
+            ids: device_path::parse_device_path(&device.path),
+            device,
+        })
+    })
+}
+
+/// 获取所有的 hid 设备，包括系统记录过但当前未连接的设备
+///
+/// 已连接的设备与 all_hid_device 相同；未连接的设备 is_present 为 false，只有 id 和 path
+pub fn all_hid_device_including_absent() -> Result<Vec<HidDevice>> {
+    let mut list = all_hid_device()?;
+    let present = present_device_paths()?;
+    for device in enum_hid_devices(false, IdScheme::default())? {
+        let mut device = match device {
+            Ok(v) => v,
+            Err(_err) => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(error = %_err, "skip hid device without container id");
+                continue;
+            },
+        };
+        if present.contains(&device.path) {
+            continue;
+        }
+        device.absent = true;
+        list.push(device);
+    }
+    Ok(list)
+}
+
+/// 当前已连接的 hid 设备接口路径，不打开设备
+///
+/// 用于区分真正被移除的设备和只是这次枚举时打开失败（如超时）的设备
+pub(crate) fn present_device_paths() -> Result<HashSet<OsString>> {
+    Ok(enum_hid_devices(true, IdScheme::default())?
+        .filter_map(|device| device.ok())
+        .map(|device| device.path)
+        .collect())
+}
+
+/// 枚举 hid 设备接口，不打开设备
+///
+/// present_only 为 false 时包括当前未连接的设备，设备 id 按 scheme 生成
+fn enum_hid_devices(present_only: bool, scheme: IdScheme) -> Result<HidInterfaces> {
+    // 1.获取 hid GUID 
+    let mut p_guid = ::windows::core::GUID::new()?;
+    unsafe {HidD_GetHidGuid(&mut p_guid)}
+    // 2.根据 HID GUID 获取HID 设备列表，接口在迭代时逐个获取
+    let device_info_set = DeviceInfoSet::new(Some(&p_guid), present_only)?;
+    Ok(HidInterfaces { device_info_set, class: p_guid, idx: 0, scheme })
+}
+
+/// enum_hid_devices 返回的迭代器，持有 DeviceInfoSet，每次 next 才获取下一个设备接口
+struct HidInterfaces {
+    device_info_set: DeviceInfoSet,
+    class: ::windows::core::GUID,
+    idx: u32,
+    scheme: IdScheme,
+}
+
+impl Iterator for HidInterfaces {
+    type Item = Result<HidDevice>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (device_interface_name, device) = self.device_info_set.device_interface(&self.class, self.idx)?;
+        self.idx += 1;
+        // 获取 container id 失败的设备作为错误项返回，由调用方决定跳过还是报告（见 all_hid_device_verbose）
+        Some(interface_device(&self.device_info_set, device_interface_name, &device, self.scheme))
+    }
 }
 
-    /// 获取所有的 hid 设备
-pub fn all_hid_device() -> Result<Vec<HidDevice>> {
-    let mut list = vec![];
-    // 1.获取 hid GUID 
+/// 根据设备接口创建 HidDevice，id 按 scheme 生成，不打开设备
+fn interface_device(device_info_set:&DeviceInfoSet, path:OsString, device_data:&SP_DEVINFO_DATA, scheme: IdScheme) -> Result<HidDevice> {
+    let id = device_info_set.get_container_id(device_data)?;
+    let container_id = to_uuid(&id);
+    let id = match scheme {
+        IdScheme::ContainerId => container_id,
+        IdScheme::ContainerPath => container_path_uuid(&id, &path),
+    };
+    let mut device = HidDevice::new(id,path);
+    device.container_id = container_id;
+    device.location = location_info(device_data.DevInst).ok();
+    Ok(device)
+}
+
+/// 根据设备实例 id（如 `HID\VID_1234&PID_5678\7&1a2b3c4d&0&0000`）打开设备并获取设备信息
+///
+/// 适合多个相同 VID/PID 的设备同时连接、已经记录了实例 id 的场景，
+/// 实例 id 不存在或没有 hid 接口时返回 Error::NotFound
+pub fn open_by_instance_id(instance_id:&str) -> Result<HidDevice> {
     let mut p_guid = ::windows::core::GUID::new()?;
     unsafe {HidD_GetHidGuid(&mut p_guid)}
-    // 2.根据 HID GUID 获取HID 设备列表
-    let device_info_set = DeviceInfoSet::new(Some(&p_guid))?;
-    for (device_interface_name, device) in
-    device_info_set.iter_device_interfaces(p_guid){
-        let id = device_info_set.get_container_id(&device)?;
-        let mut device_info = HidDevice::new(to_uuid(&id),device_interface_name);
-        if let Err(_err) = device_info.get_device_info() {
-            continue;
-        }
-        list.push(device_info)
-    }
-    Ok(list)
+    let device_info_set = DeviceInfoSet::for_instance(&p_guid, instance_id).map_err(|_| Error::NotFound)?;
+    let (path, device) = device_info_set.iter_device_interfaces(p_guid).next().ok_or(Error::NotFound)?;
+    let mut device = interface_device(&device_info_set, path, &device, IdScheme::default())?;
+    device.get_device_info()?;
+    Ok(device)
 }
 
 
+/// 根据设备接口路径获取设备的 container id，路径可以是任意类型的设备接口，不限于 hid
+///
+/// 用于把热插拔通知等只有路径的设备与其他设备关联起来，同一物理设备的各接口 container id 相同。
+/// 路径不存在时返回 Error::NotFound
+pub fn container_id_for_path(path:&OsStr) -> Result<Uuid> {
+    let (device_info_set, device) = DeviceInfoSet::for_path(path)?;
+    Ok(to_uuid(&device_info_set.get_container_id(&device)?))
+}
+
 #[cfg(test)]
 mod tests {
 
-    use crate::{hid_device::{HidDevice,all_hid_device}};
+    use crate::{hid_device::{HidDevice,ReportKind,all_hid_device,all_hid_device_verbose,all_hid_device_including_absent,iter_hid_devices}};
     #[test]                     
     fn set_output_report_test() {
         // for device in all_hid_device().unwrap() {
@@ -374,6 +2225,15 @@ mod tests {
     }
 
 
+    #[test]
+    fn get_output_report_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        let data = vec![1;2];
+        device.set_output_report(0x00, data.as_slice()).unwrap();
+        let result = device.get_output_report(0x00, 2).unwrap();
+        assert_eq!(result, data);
+    }
+
     #[test]
     fn get_feature_test() {
         let device = all_hid_device().unwrap().into_iter().find(|x| x.feature_report_byte_length == 65).unwrap();
@@ -382,6 +2242,273 @@ mod tests {
         assert_eq!(result.len(), 51);
     }
 
+    #[test]
+    fn output_assemble_large_report_test() {
+        let mut device = HidDevice::default();
+        device.output_report_byte_length = 1025;
+        let data = vec![0xAB;1024];
+        let send_data = device.output_assemble_data(0x02, &data, device.output_report_byte_length as usize).unwrap();
+        assert_eq!(send_data.len(), 1025);
+        assert_eq!(send_data[0], 0x02);
+        assert_eq!(&send_data[1..], data.as_slice());
+
+        let send_data = device.output_assemble_data(0x02, &[1,2,3], device.output_report_byte_length as usize).unwrap();
+        assert_eq!(send_data.len(), 1025);
+        assert!(send_data[4..].iter().all(|&x| x == 0));
+
+        assert!(device.output_assemble_data(0x02, &[0;1025], device.output_report_byte_length as usize).is_err());
+    }
+
+    #[test]
+    fn open_by_instance_id_test() {
+        assert!(matches!(super::open_by_instance_id(r"HID\VID_0000&PID_0000\0&0&0&0000"), Err(crate::Error::NotFound)));
+    }
+
+    #[test]
+    fn nt_status_test() {
+        // HIDP_STATUS_INVALID_PREPARSED_DATA
+        let err = windows::core::Error::from(windows::core::HRESULT(0xD011_0001u32 as i32));
+        assert_eq!(super::nt_status(&err), 0xC011_0001);
+    }
+
+    #[test]
+    fn sort_devices_test() {
+        let device = |vendor_id:u16, path:&str| {
+            let mut device = HidDevice::new(uuid::Uuid::new_v4(), path.into());
+            device.vendor_id = vendor_id;
+            device
+        };
+        let mut list = vec![
+            device(2, r"\\?\hid#vid_0002&pid_0001&mi_01#1"),
+            device(2, r"\\?\hid#vid_0002&pid_0001&mi_00#2"),
+            device(1, r"\\?\hid#vid_0001&pid_0001#3"),
+        ];
+        super::sort_devices(&mut list);
+        let paths = list.iter().map(|d| d.path.to_string_lossy().chars().last().unwrap()).collect::<String>();
+        assert_eq!(paths, "321");
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn stats_test() {
+        let device = HidDevice::default();
+        let clone = device.clone();
+        device.record_io("write", &[0;65]);
+        device.record_io("get_feature", &[0;9]);
+        device.record_io("read", &[0;65]);
+        let _ = clone.io_error(crate::Error::Timeout);
+        let stats = clone.stats();
+        assert_eq!((stats.writes, stats.bytes_written), (1, 65));
+        assert_eq!((stats.reads, stats.bytes_read), (2, 74));
+        assert_eq!(stats.errors, 1);
+    }
+
+    #[test]
+    fn diff_devices_test() {
+        let device = |path:&str| HidDevice::new(uuid::Uuid::new_v4(), path.into());
+        let old = vec![device("a"), device("b")];
+        let new = vec![device("b"), device("c")];
+        let (added, removed) = super::diff_devices(&old, &new);
+        assert_eq!(added.iter().map(|d| d.path.clone()).collect::<Vec<_>>(), vec!["c"]);
+        assert_eq!(removed.iter().map(|d| d.path.clone()).collect::<Vec<_>>(), vec!["a"]);
+        let (added, removed) = super::diff_devices(&new, &new);
+        assert!(added.is_empty() && removed.is_empty());
+    }
+
+    #[test]
+    fn short_input_report_test() {
+        // input 长度 65，设备只发送了 1 字节报告 ID + 8 字节数据
+        let mut device = HidDevice::default();
+        device.input_report_byte_length = 65;
+        let mut read_data = device.input_assemble_data(0x00, device.input_report_byte_length as usize).unwrap();
+        read_data[1..9].copy_from_slice(&[0xAB;8]);
+        let read_len = 9;
+        let data = device.strip_report_id(0x00, super::received_report(read_data, read_len), 64);
+        assert_eq!(data.len(), read_len as usize - 1);
+        assert_eq!(data, vec![0xAB;8]);
+    }
+
+    #[test]
+    fn keep_report_id_test() {
+        let mut device = HidDevice::default();
+        assert!(device.strips_report_id());
+        // 数据恰好以报告 ID 的值开头
+        assert_eq!(device.strip_report_id(0x00, vec![0x00, 0x00, 0x01, 0x02], 2), vec![0x00, 0x01]);
+        device.set_strip_report_id(false);
+        assert_eq!(device.strip_report_id(0x00, vec![0x00, 0x00, 0x01, 0x02], 2), vec![0x00, 0x00, 0x01]);
+        assert_eq!(device.clone().strip_report_id(0x00, vec![0x00, 0x05], 8), vec![0x00, 0x05]);
+    }
+
+    #[test]
+    fn output_assemble_report_id_zero_test() {
+        // 不使用编号报告的设备，output 长度 65 = 1 字节报告 ID(0) + 64 字节数据
+        let mut device = HidDevice::default();
+        device.output_report_byte_length = 65;
+        let data = vec![0xAB;64];
+        let send_data = device.output_assemble_data(0x00, &data, device.output_report_byte_length as usize).unwrap();
+        assert_eq!(send_data.len(), 65);
+        assert_eq!(send_data[0], 0x00);
+        assert_eq!(&send_data[1..], data.as_slice());
+
+        let send_data = device.output_assemble_data(0x00, &[1], device.output_report_byte_length as usize).unwrap();
+        assert_eq!(send_data, [&[0x00, 1][..], &[0;63][..]].concat());
+
+        // 64 字节数据刚好占满，65 字节在打开设备之前就被拒绝
+        assert!(device.output_assemble_data(0x00, &data, 65).is_ok());
+        assert!(matches!(device.write(0x00, &[0;65]), Err(crate::Error::DataOverlength)));
+        assert!(matches!(device.write_exact(0x00, &[0;65]), Err(crate::Error::DataOverlength)));
+        assert!(matches!(device.set_output_report(0x00, &[0;65]), Err(crate::Error::DataOverlength)));
+    }
+
+    #[test]
+    fn report_bits_test() {
+        use windows::Win32::Devices::HumanInterfaceDevice::{
+            HIDP_BUTTON_CAPS, HIDP_BUTTON_CAPS_0, HIDP_BUTTON_CAPS_0_1, HIDP_VALUE_CAPS,
+        };
+        use windows::Win32::Foundation::BOOLEAN;
+        // 报告 1：8 个按钮 + 2 个 8 位的值；报告 2：1 个 16 位的值，另有一个别名不计入
+        let buttons = HIDP_BUTTON_CAPS {
+            ReportID: 1,
+            IsRange: BOOLEAN(1),
+            Anonymous: HIDP_BUTTON_CAPS_0 { Range: HIDP_BUTTON_CAPS_0_1 { UsageMin: 1, UsageMax: 8, ..Default::default() } },
+            ..Default::default()
+        };
+        let values = [
+            HIDP_VALUE_CAPS { ReportID: 1, BitSize: 8, ReportCount: 2, ..Default::default() },
+            HIDP_VALUE_CAPS { ReportID: 2, BitSize: 16, ReportCount: 1, ..Default::default() },
+            HIDP_VALUE_CAPS { ReportID: 2, BitSize: 16, ReportCount: 1, IsAlias: BOOLEAN(1), ..Default::default() },
+        ];
+        let bits = super::report_bits(&[buttons], &values);
+        assert_eq!(bits.into_iter().collect::<Vec<_>>(), vec![(1, 24), (2, 16)]);
+    }
+
+    #[test]
+    fn report_map_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        let map = device.report_map().unwrap();
+        println!("report map:{:?}", map);
+        assert!(map.values().all(|x| x.input.map_or(true, |len| len <= 65)));
+    }
+
+    #[test]
+    fn iter_hid_devices_test() {
+        let mut device = iter_hid_devices().filter_map(|x| x.ok()).next().unwrap();
+        assert_eq!(device.input_report_byte_length, 0);
+        device.load_info().unwrap();
+        assert_ne!(device.vendor_id, 0);
+    }
+
+    #[test]
+    fn all_hid_device_verbose_test() {
+        let devices = all_hid_device_verbose();
+        let failed = devices.iter().filter(|x| x.is_err()).count();
+        println!("{} devices, {} failed to open", devices.len(), failed);
+        assert_eq!(devices.len() - failed, all_hid_device().unwrap().len());
+    }
+
+    #[test]
+    fn all_hid_device_including_absent_test() {
+        let devices = all_hid_device_including_absent().unwrap();
+        let present = devices.iter().filter(|x| x.is_present()).count();
+        assert_eq!(present, all_hid_device().unwrap().len());
+        for device in devices.iter().filter(|x| !x.is_present()) {
+            assert!(!device.is_openable());
+        }
+    }
+
+    #[test]
+    fn supports_report_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.output_report_byte_length == 65).unwrap();
+        assert!(device.supports_report(0x00, ReportKind::Output).unwrap());
+        assert!(!device.supports_report(0xEE, ReportKind::Output).unwrap());
+    }
+
+    #[test]
+    fn uses_report_ids_test() {
+        for device in all_hid_device().unwrap() {
+            println!("{:?} uses_report_ids:{}", device.path, device.uses_report_ids());
+            // 与各类报告 caps 中的报告 ID 一致，读取失败的类型不计入
+            let numbered = [ReportKind::Input, ReportKind::Output, ReportKind::Feature].iter()
+                .any(|kind| device.report_ids(*kind).unwrap_or_default().iter().any(|&id| id != 0));
+            assert_eq!(device.uses_report_ids(), numbered);
+        }
+    }
+
+    #[test]
+    fn link_collections_test() {
+        let device = all_hid_device().unwrap().pop().unwrap();
+        let nodes = device.link_collections().unwrap();
+        println!("nodes:{:?}", nodes);
+        assert_eq!(nodes[0].usage_page, device.usage_page);
+        assert_eq!(nodes[0].usage, device.usage);
+        assert!(device.usages.contains(&(device.usage_page, device.usage)));
+    }
+
+    #[test]
+    fn read_only_open_test() {
+        // 系统键盘拒绝读写权限，应退回 0 权限打开，仍可读取属性和字符串
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.usage_page == 0x01 && x.usage == 0x06).unwrap();
+        assert!(device.is_openable());
+        assert!(device.strings().is_ok());
+        assert!(matches!(device.write(0, &[0]), Err(crate::Error::WriteNotPermitted)));
+        device.open().unwrap();
+        assert!(!device.can_write());
+        device.close();
+    }
+
+    #[test]
+    fn strings_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        let strings = device.strings().unwrap();
+        println!("strings:{:?}", strings);
+        assert!(strings.manufacturer.is_some() || strings.product.is_some());
+    }
+
+    #[test]
+    fn refresh_attributes_test() {
+        let mut device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        let (vendor_id, product_id, release) = (device.vendor_id, device.product_id, device.release);
+        device.release = 0;
+        device.refresh_attributes().unwrap();
+        assert_eq!((device.vendor_id, device.product_id, device.release), (vendor_id, product_id, release));
+    }
+
+    #[test]
+    fn open_path_test() {
+        let device = all_hid_device().unwrap().pop().unwrap();
+        let opened = HidDevice::open_path(&device.path).unwrap();
+        assert_eq!(opened.vendor_id, device.vendor_id);
+        assert_eq!(opened.product_id, device.product_id);
+        assert_eq!(opened.id, device.id);
+        assert_eq!(opened.container_id, device.container_id);
+    }
+
+    #[test]
+    fn read_feature_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.feature_report_byte_length == 65).unwrap();
+        let (report_id, result) = device.read_feature(0x00).unwrap();
+        println!("report_id:{} result:{:?}", report_id, result);
+        assert_eq!(result.len(), 64);
+    }
+
+    #[test]
+    fn get_feature_full_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.feature_report_byte_length == 65).unwrap();
+        let result = device.get_feature_full(0x00).unwrap();
+        assert_eq!(result.len(), 65);
+        let (report_id, data) = device.read_feature(0x00).unwrap();
+        assert_eq!(result[0], report_id);
+        device.set_feature_report(report_id, &data).unwrap();
+    }
+
+    #[test]
+    fn subscribe_feature_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.feature_report_byte_length == 65).unwrap();
+        let receiver = device.subscribe_feature(0x00, std::time::Duration::from_millis(100)).unwrap();
+        let result = receiver.recv().unwrap().unwrap();
+        assert_eq!(result.len(), 64);
+    }
+
     #[test]
     fn write_test() {
         let device = all_hid_device().unwrap().into_iter().find(|x| x.feature_report_byte_length == 65).unwrap();
@@ -392,6 +2519,197 @@ mod tests {
         assert_eq!(write_len, 64);
     }
 
+    #[test]
+    fn concurrent_write_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        let workers = (0..4).map(|_| {
+            let device = device.clone();
+            std::thread::spawn(move || {
+                for _ in 0..10 {
+                    device.write(0x00, &[1, 2]).unwrap();
+                }
+            })
+        }).collect::<Vec<_>>();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn write_exact_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.output_report_byte_length == 65).unwrap();
+        let data = [1;8];
+        let write_len = device.write_exact(0x00, &data).unwrap();
+        assert_eq!(write_len, 9);
+    }
+
+    #[test]
+    fn send_output_report_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.output_report_byte_length == 65).unwrap();
+        let transport = device.send_output_report(0x00, &[1;8]).unwrap();
+        println!("transport:{:?}", transport);
+        // 同一设备每次选择的传输方式相同
+        assert_eq!(device.send_output_report(0x00, &[1;8]).unwrap(), transport);
+    }
+
+    #[test]
+    fn get_input_report_any_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        let (transport, data) = device.get_input_report_any(0x00, 64).unwrap();
+        println!("transport:{:?} data:{:?}", transport, data);
+        assert_eq!(data.len(), 64);
+    }
+
+    #[test]
+    fn write_during_read_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65 && x.output_report_byte_length == 65).unwrap();
+        let reader = device.clone();
+        let handle = std::thread::spawn(move || reader.read_until(0x00, 64, std::time::Instant::now() + std::time::Duration::from_millis(500)));
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        // 读取等待期间写入不会被阻塞，写入结束也不会关闭读取正在使用的句柄
+        let start = std::time::Instant::now();
+        device.write(0x00, &[1;8]).unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_millis(400));
+        assert!(handle.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn open_close_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.output_report_byte_length == 65).unwrap();
+        device.open().unwrap();
+        device.open().unwrap();
+        let handle = device.raw_handle().unwrap();
+        device.write(0x00, &[1;8]).unwrap();
+        assert_eq!(device.raw_handle().unwrap(), handle);
+        assert!(!device.close());
+        assert!(device.close());
+        assert!(device.device_handle.handle.read().unwrap().is_none());
+    }
+
+    #[test]
+    fn refresh_info_keeps_open_test() {
+        let mut device = all_hid_device().unwrap().into_iter().find(|x| x.output_report_byte_length == 65).unwrap();
+        device.open().unwrap();
+        device.refresh_info().unwrap();
+        // refresh_info 只关闭句柄，open 仍然有效，写入后句柄保持打开
+        device.write(0x00, &[1;8]).unwrap();
+        assert!(device.device_handle.handle.read().unwrap().is_some());
+        assert!(device.close());
+        assert!(device.device_handle.handle.read().unwrap().is_none());
+    }
+
+    #[test]
+    fn check_report_id_cache_test() {
+        // 无法获取 preparsed data 时不检查，失败同样缓存
+        let device = HidDevice::new(uuid::Uuid::new_v4(), "test".into());
+        assert!(device.check_report_id(ReportKind::Output, 0x05).is_ok());
+        assert!(matches!(*device.device_handle.report_ids.lock().unwrap(), Some(None)));
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        assert!(device.check_report_id(ReportKind::Input, 0x00).is_ok());
+        assert!(matches!(*device.device_handle.report_ids.lock().unwrap(), Some(Some(_))));
+    }
+
+    #[test]
+    fn clone_independent_test() {
+        let device = HidDevice::new(uuid::Uuid::new_v4(), "test".into());
+        assert!(std::sync::Arc::ptr_eq(&device.device_handle, &device.clone().device_handle));
+        let independent = device.clone_independent();
+        assert!(!std::sync::Arc::ptr_eq(&device.device_handle, &independent.device_handle));
+        assert_eq!(independent.id, device.id);
+        assert_eq!(independent.path, device.path);
+    }
+
+    #[test]
+    fn open_flags_test() {
+        let mut device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        device.set_open_options(super::OpenOptions {
+            flags: windows::Win32::Storage::FileSystem::FILE_FLAG_NO_BUFFERING,
+            ..Default::default()
+        });
+        assert!(device.is_openable());
+    }
+
+    #[test]
+    fn refresh_info_test() {
+        let mut device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        assert!(!device.refresh_info().unwrap());
+        device.input_report_byte_length = 0;
+        assert!(device.refresh_info().unwrap());
+        assert_eq!(device.input_report_byte_length, 65);
+    }
+
+    #[test]
+    fn feature_report_ids_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.feature_report_byte_length > 0).unwrap();
+        let ids = device.feature_report_ids().unwrap();
+        println!("feature report ids:{:?}", ids);
+        assert!(!ids.is_empty());
+        assert_eq!(device.feature_report_ids().unwrap(), ids);
+    }
+
+    #[test]
+    fn language_ids_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        let language_ids = device.language_ids().unwrap();
+        println!("language ids:{:04X?}", language_ids);
+        // 0 号字符串描述符中是 LANGID 列表，不会有 0
+        assert!(language_ids.iter().all(|&id| id != 0));
+        if let Some(&language_id) = language_ids.first() {
+            println!("string 1:{:?}", device.indexed_string(1, language_id).unwrap());
+        }
+    }
+
+    #[test]
+    fn invalid_report_id_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.output_report_byte_length == 65).unwrap();
+        let ids = device.report_ids(ReportKind::Output).unwrap();
+        let invalid = (0..=u8::MAX).find(|id| !ids.contains(id)).unwrap();
+        assert!(matches!(device.write(invalid, &[1;8]), Err(crate::Error::InvalidReportId(v)) if v == invalid));
+    }
+
+    #[test]
+    fn raw_handle_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        let handle = device.raw_handle().unwrap();
+        assert!(!handle.is_invalid());
+        assert_eq!(device.raw_handle().unwrap(), handle);
+        device.close_device();
+    }
+
+    #[test]
+    fn write_timeout_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.output_report_byte_length == 65).unwrap();
+        let data = [1;8];
+        match device.write_timeout(0x00, &data, std::time::Duration::from_millis(500)) {
+            Ok(write_len) => assert_eq!(write_len, 65),
+            Err(crate::Error::Timeout) => {}
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn write_timeout_zero_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.output_report_byte_length == 65).unwrap();
+        let data = [1;8];
+        for _ in 0..10 {
+            match device.write_timeout(0x00, &data, std::time::Duration::ZERO) {
+                Ok(write_len) => assert_eq!(write_len, 65),
+                Err(crate::Error::Timeout) => {}
+                Err(e) => panic!("{}", e),
+            }
+        }
+        // 被取消的写入已经结束，之后的写入不受影响
+        assert_eq!(device.write_timeout(0x00, &data, std::time::Duration::from_millis(500)).unwrap(), 65);
+    }
+
+    #[test]
+    fn timeout_millis_test() {
+        assert_eq!(super::timeout_millis(std::time::Duration::ZERO), 0);
+        assert_eq!(super::timeout_millis(std::time::Duration::from_micros(500)), 1);
+        assert_eq!(super::timeout_millis(std::time::Duration::from_millis(500)), 500);
+        assert_eq!(super::timeout_millis(std::time::Duration::MAX), super::INFINITE - 1);
+    }
+
     #[test]
     fn read_test() {
         let device = all_hid_device().unwrap().into_iter().find(|x| x.output_report_byte_length == 65).unwrap();
@@ -401,6 +2719,34 @@ mod tests {
         assert_eq!(result.len(), 64);
     }
 
+    #[test]
+    fn read_report_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        let (report_id, data) = device.read_report(0x00).unwrap();
+        println!("report_id:{} data:{:?}", report_id, data);
+        assert_eq!(data.len(), 64);
+    }
+
+    #[test]
+    fn read_until_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_millis(500);
+        let reports = device.read_until(0x00, 64, deadline).unwrap();
+        println!("reports:{}", reports.len());
+        assert!(reports.iter().all(|report| report.len() <= 64));
+        assert!(std::time::Instant::now() < deadline + std::time::Duration::from_millis(100));
+    }
+
+    #[test]
+    fn discard_input_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        device.read_flush().unwrap();
+        assert!(device.device_handle.pending.lock().unwrap().is_none());
+        let count = device.discard_input().unwrap();
+        println!("discard:{}", count);
+        device.close_device();
+    }
+
     #[test]
     fn read_continuous_test() {
         let device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
@@ -413,4 +2759,171 @@ mod tests {
         device.close_device();
         assert_eq!(1, 1);
     }
+
+    #[test]
+    fn usb_speed_test() {
+        let mut device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        assert!(device.usb_version.is_none());
+        device.load_usb_info().unwrap();
+        println!("usb_version:{:?} speed:{:?}", device.usb_version, device.speed);
+        assert!(device.usb_version.is_some());
+        assert!(device.speed.is_some());
+    }
+
+    #[test]
+    fn usb_port_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        let port = device.usb_port().unwrap();
+        assert!(device.location.unwrap().contains(&format!("Port_#{:04}", port)));
+    }
+
+    #[test]
+    fn report_reader_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        let mut reader = device.report_reader().unwrap();
+        for _ in 0..3 {
+            let data = reader.read(0x00, 64).unwrap();
+            assert!(data.len() <= 64);
+        }
+        assert_eq!(reader.device().path, device.path);
+    }
+
+    #[test]
+    fn collection_contains_test() {
+        let node = |parent| crate::hid_device::LinkCollectionNode {
+            usage: 0, usage_page: 0, parent, number_of_children: 0, next_sibling: 0, first_child: 0, collection_type: 0x01, is_alias: false,
+        };
+        // 0 为顶层集合，1、2 为其子集合，3 为 2 的子集合
+        let nodes = vec![node(0), node(0), node(0), node(2)];
+        assert!(super::collection_contains(&nodes, 0, 3));
+        assert!(super::collection_contains(&nodes, 2, 3));
+        assert!(!super::collection_contains(&nodes, 1, 3));
+        assert!(!super::collection_contains(&nodes, 1, 0));
+        assert!(super::collection_contains(&nodes, 1, 1));
+    }
+
+    #[test]
+    fn hid_device_entries_test() {
+        let entries = crate::hid_device::hid_device_entries().filter_map(|x| x.ok()).collect::<Vec<_>>();
+        assert!(!entries.is_empty());
+        let mut opened = 0;
+        for entry in entries.iter() {
+            match entry.try_open() {
+                Ok(device) => {
+                    assert_eq!(device.path, entry.path);
+                    opened += 1;
+                },
+                Err(err) => println!("{:?}: {}", entry.path, err),
+            }
+        }
+        assert!(opened > 0);
+    }
+
+    #[test]
+    fn configuration_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        let mut config = device.get_configuration().unwrap();
+        println!("ring_buffer_size:{}", config.ring_buffer_size);
+        assert!(config.ring_buffer_size > 0);
+        // 未修改时重复读取得到相同的配置
+        assert_eq!(device.get_configuration().unwrap(), config);
+        let original = config.ring_buffer_size;
+        config.ring_buffer_size = original + 1;
+        if device.set_configuration(config).is_ok() {
+            config.ring_buffer_size = original;
+            device.set_configuration(config).unwrap();
+        }
+    }
+
+    #[test]
+    fn transact_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        match device.transact(0x00, &[0x01], 64, std::time::Duration::from_millis(500)) {
+            Ok(response) => assert!(response.len() <= 64),
+            Err(err) => assert!(matches!(err, crate::Error::Timeout), "{}", err),
+        }
+        let too_long = vec![0u8; device.output_report_byte_length as usize];
+        assert!(matches!(device.transact(0x00, &too_long, 64, std::time::Duration::from_millis(10)), Err(crate::Error::DataOverlength)));
+        // 超时为 0 时未完成的读写被取消，之后设备仍可正常使用
+        match device.transact(0x00, &[0x01], 64, std::time::Duration::ZERO) {
+            Ok(response) => assert!(response.len() <= 64),
+            Err(err) => assert!(matches!(err, crate::Error::Timeout), "{}", err),
+        }
+        assert!(device.write(0x00, &[0x01]).is_ok());
+    }
+
+    #[test]
+    fn all_hid_device_on_port_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        let location = device.location.clone().unwrap();
+        let devices = crate::hid_device::all_hid_device_on_port(&location.to_uppercase()).unwrap();
+        assert!(devices.iter().any(|x| x.path == device.path));
+        assert!(devices.iter().all(|x| x.container_id == device.container_id));
+        assert!(crate::hid_device::all_hid_device_on_port("Port_#9999.Hub_#9999").unwrap().is_empty());
+    }
+
+    #[test]
+    fn container_id_for_path_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        assert_eq!(crate::hid_device::container_id_for_path(&device.path).unwrap(), device.container_id);
+        let missing = std::ffi::OsStr::new(r"\\?\hid#vid_0000&pid_0000#0&0&0&0000#{4d1e55b2-f16f-11cf-88cb-001111000030}");
+        assert!(matches!(crate::hid_device::container_id_for_path(missing), Err(crate::Error::NotFound)));
+    }
+
+    #[test]
+    fn get_feature_report_timeout_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.feature_report_byte_length > 1).unwrap();
+        let report_id = device.feature_report_ids().unwrap()[0];
+        let data_len = device.feature_report_byte_length as usize - 1;
+        match device.get_feature_report_timeout(report_id, data_len, std::time::Duration::from_millis(500)).unwrap() {
+            Some(data) => assert!(data.len() <= data_len),
+            None => println!("get feature timed out"),
+        }
+        for timeout in [std::time::Duration::ZERO, std::time::Duration::from_micros(100)] {
+            match device.get_feature_report_timeout(report_id, data_len, timeout).unwrap() {
+                Some(data) => assert!(data.len() <= data_len),
+                None => println!("get feature timed out"),
+            }
+        }
+        assert!(device.get_feature_report_timeout(report_id, data_len, std::time::Duration::from_millis(500)).is_ok());
+    }
+
+    #[test]
+    fn diagnostics_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        let report = device.diagnostics().unwrap();
+        println!("{}", report);
+        assert!(report.contains(&format!("0x{:04X}", device.vendor_id)));
+        assert!(report.lines().count() > 10);
+    }
+
+    #[test]
+    fn subscribe_changes_test() {
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        let receiver = device.subscribe_changes(0x00).unwrap();
+        let mut last: Option<Vec<u8>> = None;
+        for _ in 0..10 {
+            let data = match receiver.recv_timeout(std::time::Duration::from_secs(1)) {
+                Ok(data) => data.unwrap(),
+                Err(_) => break,
+            };
+            assert_ne!(last.as_ref(), Some(&data));
+            last = Some(data);
+        }
+    }
+
+    #[test]
+    fn zero_timeout_read_test() {
+        // 超时为 0 的读取未完成时要取消并等待结束，之后驱动不能再写入已经释放的缓冲区
+        let device = all_hid_device().unwrap().into_iter().find(|x| x.input_report_byte_length == 65).unwrap();
+        device.open().unwrap();
+        let handle = device.raw_handle().unwrap();
+        for _ in 0..100 {
+            let mut buffer = vec![0u8; 65];
+            assert!(device.read_overlapped(handle, &mut buffer, 0).is_ok());
+        }
+        assert!(device.has_pending_input().is_ok());
+        assert!(device.read_until(0x00, 64, std::time::Instant::now()).is_ok());
+        device.close();
+    }
 }
\ No newline at end of file
@@ -12,10 +12,10 @@
 //!         match read.recv() {
 //!             Ok(v) => {
 //!                 match v {
-//!                     CentralEvent::DeviceAdd(id) => {
+//!                     CentralEvent::DeviceAdd { id, .. } => {
 //!                         println!("Add:{:?}",id);
 //!                     },
-//!                     CentralEvent::DeviceRemove(device) => {
+//!                     CentralEvent::DeviceRemove { device, .. } => {
 //!                         println!("Remove:{:?}",device.id);
 //!                     },
 //!                 }
@@ -24,6 +24,15 @@
 //!         }
 //!     }
 //!```
+//!
+//! ### 不需要热插拔监听时
+//! ```no_run
+//!     let devices = usb_manager::list_devices().unwrap();
+//!     let device = usb_manager::open_device(&devices[0].path).unwrap();
+//!     device.write(0x00, &[0x01, 0x02]).unwrap();
+//!     let report = device.read(0x00, 2).unwrap();
+//!     println!("{:?}", report);
+//!```
 
 
 mod device_interface;
@@ -33,11 +42,16 @@ mod manager;
 mod utils;
 pub mod adapter;
 pub mod hid_device;
+pub mod device_path;
+pub mod usb_device;
+pub mod usage;
+pub mod filter;
+pub mod write_queue;
 
 
 use thiserror::Error;
-use std::result;
-use windows::Win32::Foundation::GetLastError;
+use std::{ffi::OsStr, result, time::SystemTime};
+use windows::{core::HRESULT, Win32::Foundation::GetLastError};
 use uuid::Uuid;
 use hid_device::HidDevice;
 
@@ -52,11 +66,37 @@ pub enum Error {
     #[error("Device opening error")]
     OpenError,
 
+    /// 打开设备时 ERROR_SHARING_VIOLATION，设备已被其他程序独占打开
+    #[error("Device is in use by another application")]
+    Busy,
+
     #[error("Device not found")]
     NotFound,
 
     #[error("Data exceeds the maximum length")]
     DataOverlength,
+
+    /// 设备没有该 ID 的报告，在发起传输之前根据报告描述符检查
+    #[error("Report id 0x{0:02X} is not supported by the device")]
+    InvalidReportId(u8),
+
+    /// link collection 下标超出 HidDevice::link_collections 的范围
+    #[error("Link collection {0} does not exist")]
+    InvalidCollection(u16),
+
+    #[error("Device was opened without write access")]
+    WriteNotPermitted,
+
+    /// overlapped 传输在超时前未完成，已通过 CancelIoEx 取消
+    #[error("Operation timed out")]
+    Timeout,
+
+    /// HidP_* 解析 preparsed data 失败，通常是设备的报告描述符有误，字段为 HidP 返回的 NTSTATUS
+    #[error("Invalid preparsed data, the report descriptor may be malformed (HidP status 0x{0:08X})")]
+    InvalidPreparsedData(u32),
+
+    #[error("Windows error 0x{:08X}", _0.0)]
+    Windows(HRESULT),
     
     #[error("{}", _0)]
     Other(Box<dyn std::error::Error + Send + Sync>),
@@ -68,13 +108,47 @@ impl Error {
     }
 }
 
+impl From<windows::core::Error> for Error {
+    fn from(err: windows::core::Error) -> Self {
+        let code = err.code();
+        // FACILITY_WIN32 的 HRESULT 还原为 Win32 错误码
+        if (code.0 as u32) & 0xFFFF0000 == 0x80070000 {
+            Self::Win32(code.0 as u32 & 0xFFFF)
+        } else {
+            Self::Windows(code)
+        }
+    }
+}
+
 
 pub type Result<T> = result::Result<T, Error>;
 
+/// 获取当前所有 hid 设备，不启动热插拔监听线程
+pub fn list_devices() -> Result<Vec<HidDevice>> {
+    hid_device::all_hid_device()
+}
+
+/// 根据设备路径打开设备，不启动热插拔监听线程
+pub fn open_device(path: &OsStr) -> Result<HidDevice> {
+    HidDevice::open_path(path)
+}
+
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CentralEvent {
-    DeviceAdd(Uuid),
-    DeviceRemove(HidDevice),
+    /// time 为事件产生的时间，不需要时用 `..` 忽略
+    DeviceAdd { id: Uuid, time: SystemTime },
+    DeviceRemove { device: HidDevice, time: SystemTime },
+}
+
+impl CentralEvent {
+    /// 事件产生的时间
+    pub fn timestamp(&self) -> SystemTime {
+        match self {
+            Self::DeviceAdd { time, .. } => *time,
+            Self::DeviceRemove { time, .. } => *time,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -82,6 +156,35 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn from_windows_error_test() {
+        let err: Error = windows::core::Error::new(HRESULT(0x80070005u32 as i32), "".into()).into();
+        assert!(matches!(err, Error::Win32(5)));
+        let err: Error = windows::core::Error::new(HRESULT(0x8000FFFFu32 as i32), "".into()).into();
+        assert!(matches!(err, Error::Windows(HRESULT(code)) if code == 0x8000FFFFu32 as i32));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn central_event_serde_test() {
+        let mut device = HidDevice::new(Uuid::new_v4(), r"\\?\hid#vid_1234&pid_5678#1&2&3&0#{4d1e55b2-f16f-11cf-88cb-001111000030}".into());
+        device.vendor_id = 0x1234;
+        device.product_id = 0x5678;
+        let time = SystemTime::now();
+        let json = serde_json::to_string(&CentralEvent::DeviceRemove { device: device.clone(), time }).unwrap();
+        assert!(json.contains(&device.id.to_string()));
+        match serde_json::from_str::<CentralEvent>(&json).unwrap() {
+            CentralEvent::DeviceRemove { device: v, time: t } => {
+                assert_eq!(t, time);
+                assert_eq!(v.id, device.id);
+                assert_eq!(v.path, device.path);
+                assert_eq!(v.vendor_id, device.vendor_id);
+                assert_eq!(v.product_id, device.product_id);
+            },
+            _ => panic!("unexpected event"),
+        }
+    }
+
     #[test]
     fn it_works() {
         
@@ -95,10 +198,10 @@ mod tests {
             match read.recv() {
                 Ok(v) => {
                     match v {
-                        CentralEvent::DeviceAdd(id) => {
+                        CentralEvent::DeviceAdd { id, .. } => {
                             println!("Add:{:?}",id);
                         },
-                        CentralEvent::DeviceRemove(device) => {
+                        CentralEvent::DeviceRemove { device, .. } => {
                             println!("Remove:{:?}",device.id);
                         },
                     }
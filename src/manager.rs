@@ -1,18 +1,103 @@
 
+use std::{ffi::OsStr, fmt::{self, Debug, Formatter}, ops::Deref, panic::{catch_unwind, AssertUnwindSafe}, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc, Mutex, MutexGuard}, time::SystemTime};
 use dashmap::{mapref::one::RefMut, DashMap};
 use crate::CentralEvent;
 
 use super::{hid_device::HidDevice};
 use uuid::Uuid;
 use anyhow::{Result};
-use crossbeam_channel::{unbounded,Receiver,Sender};
+use crossbeam_channel::{bounded,unbounded,Receiver,Sender,TrySendError};
 
+/// 交给 Manager::emit 的设备变化，由 Manager 补上事件时间
+pub enum DeviceChange {
+    Add(Uuid),
+    Remove(HidDevice),
+}
+
+impl DeviceChange {
+    fn into_event(self) -> CentralEvent {
+        let time = SystemTime::now();
+        match self {
+            Self::Add(id) => CentralEvent::DeviceAdd { id, time },
+            Self::Remove(device) => CentralEvent::DeviceRemove { device, time },
+        }
+    }
+}
+
+/// on_event 注册的事件回调
+#[derive(Default, Clone)]
+struct Callbacks(Arc<Mutex<Vec<Arc<dyn Fn(CentralEvent) + Send + Sync>>>>);
+
+impl Debug for Callbacks {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Callbacks({})", self.0.lock().unwrap().len())
+    }
+}
+
+/// waiter 登记的等待者
+#[derive(Default, Clone)]
+struct Waiters(Arc<Mutex<Vec<Sender<CentralEvent>>>>);
+
+impl Debug for Waiters {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Waiters({})", self.0.lock().unwrap().len())
+    }
+}
+
+/// 存活的接收端或等待者计数，clone 时加一，drop 时减一
+#[derive(Debug)]
+pub struct Subscription(Arc<AtomicUsize>);
+
+impl Subscription {
+    fn new(count: &Arc<AtomicUsize>) -> Self {
+        count.fetch_add(1, Ordering::Relaxed);
+        Self(count.clone())
+    }
+}
+
+impl Clone for Subscription {
+    fn clone(&self) -> Self {
+        Self::new(&self.0)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Manager::waiter 返回的等待者，只观察设备的增减，不消费事件队列
+///
+/// 存活期间 Manager 不会进入空闲状态
+#[derive(Debug)]
+pub struct Waiter {
+    receiver: Receiver<CentralEvent>,
+    _waiting: Subscription,
+}
+
+impl Deref for Waiter {
+    type Target = Receiver<CentralEvent>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.receiver
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Manager{
     devices: DashMap<Uuid, HidDevice>,
     receiver: Receiver<CentralEvent>,
     sender: Sender<CentralEvent>,
+    subscribers: Arc<AtomicUsize>,  // 存活的 subscribe 接收端个数
+    subscribed: Arc<AtomicBool>,    // 是否调用过 subscribe
+    listened: Arc<AtomicBool>,      // 是否发出过事件队列的接收端
+    callbacks: Callbacks,
+    paused: Arc<Mutex<Option<Vec<CentralEvent>>>>, // pause_events 之后产生、尚未发出的事件
+    waiters: Waiters,
+    waiting: Arc<AtomicUsize>,      // 存活的等待者个数
+    dropped: Arc<AtomicUsize>,      // 队列满时丢弃的事件数
+    changing: Arc<Mutex<()>>,       // 同一时间只有一个线程对比设备列表，见 lock_changes
 }
 
 impl Manager {
@@ -22,19 +107,155 @@ impl Manager {
             devices:DashMap::new(),
             receiver, 
             sender,
+            subscribers: Arc::new(AtomicUsize::new(0)),
+            subscribed: Arc::new(AtomicBool::new(false)),
+            listened: Arc::new(AtomicBool::new(false)),
+            callbacks: Callbacks::default(),
+            paused: Arc::new(Mutex::new(None)),
+            waiters: Waiters::default(),
+            waiting: Arc::new(AtomicUsize::new(0)),
+            dropped: Arc::new(AtomicUsize::new(0)),
+            changing: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// 事件队列有容量上限，队列满时丢弃最旧的事件，丢弃的数量见 dropped_events
+    ///
+    /// capacity 为 0 时按 1 处理
+    pub fn with_capacity(capacity: usize) -> Self {
+        let (sender,receiver) = bounded(capacity.max(1));
+        Self{
+            devices:DashMap::new(),
+            receiver, 
+            sender,
+            subscribers: Arc::new(AtomicUsize::new(0)),
+            subscribed: Arc::new(AtomicBool::new(false)),
+            listened: Arc::new(AtomicBool::new(false)),
+            callbacks: Callbacks::default(),
+            paused: Arc::new(Mutex::new(None)),
+            waiters: Waiters::default(),
+            waiting: Arc::new(AtomicUsize::new(0)),
+            dropped: Arc::new(AtomicUsize::new(0)),
+            changing: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// 发出设备变化，事件时间在这里统一记录
+    pub fn emit(&self, change: DeviceChange) {
+        let event = change.into_event();
+        self.notify(&event);
+        let mut paused = self.paused.lock().unwrap();
+        if let Some(events) = paused.as_mut() {
+            events.push(event);
+            return;
+        }
+        drop(paused);
+        self.forward(event);
+    }
+
+    /// 登记一个等待者，之后每个设备的增减都会发给它，包括暂停期间的变化
+    ///
+    /// 不登记为事件接收端，不影响 events 返回的接收端收到的事件
+    pub fn waiter(&self) -> Waiter {
+        let (sender, receiver) = unbounded();
+        self.waiters.0.lock().unwrap().push(sender);
+        Waiter { receiver, _waiting: Subscription::new(&self.waiting) }
+    }
+
+    /// 只通知等待者，用于不需要产生事件的设备
+    pub fn notify_waiters(&self, change: DeviceChange) {
+        self.notify(&change.into_event());
+    }
+
+    fn notify(&self, event: &CentralEvent) {
+        // 等待者被 drop 后发送失败，顺便移除
+        self.waiters.0.lock().unwrap().retain(|sender| sender.send(event.clone()).is_ok());
+    }
+
+    /// 暂停发出事件，之后的事件先保存下来，resume_events 时合并后发出
+    ///
+    /// 已经暂停时不做任何事
+    pub fn pause_events(&self) {
+        let mut paused = self.paused.lock().unwrap();
+        if paused.is_none() {
+            *paused = Some(vec![]);
         }
     }
 
-    pub fn emit(&self, event: CentralEvent) {
-        if let Err(err) = self.sender.send(event) {
-            println!("send event error: {}",err);
+    /// 恢复发出事件，并发出暂停期间每个设备的净变化，见 net_events
+    pub fn resume_events(&self) {
+        // 先取出暂停期间的事件再释放锁，回调中调用 pause_events、emit 不会死锁
+        let events = self.paused.lock().unwrap().take().unwrap_or_default();
+        for event in net_events(events) {
+            self.forward(event);
         }
     }
 
+    /// 把事件交给回调和事件队列
+    ///
+    /// 调用回调时不持有锁，回调中可以调用 on_event；回调 panic 时跳过，不影响其他回调
+    fn forward(&self, event: CentralEvent) {
+        let callbacks = self.callbacks.0.lock().unwrap().clone();
+        for callback in callbacks.iter() {
+            let _ = catch_unwind(AssertUnwindSafe(|| callback(event.clone())));
+        }
+        // 只用回调、从未调用过 events 时不再缓存事件
+        if !callbacks.is_empty() && !self.listened.load(Ordering::Relaxed) {
+            return;
+        }
+        // manager 自己持有接收端，发送只会因为队列满而失败
+        if let Err(TrySendError::Full(event)) = self.sender.try_send(event) {
+            let _ = self.receiver.try_recv();
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            // 其他线程同时发送时队列可能又满了，此时丢弃新事件
+            if self.sender.try_send(event).is_err() {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// 队列满时丢弃的事件总数，只有 with_capacity 创建的 manager 会丢弃事件
+    pub fn dropped_events(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
     pub fn event_channel(&self) -> Receiver<CentralEvent>{
+        self.listened.store(true, Ordering::Relaxed);
         self.receiver.clone()
     }
 
+    /// 登记一个计入空闲判断的事件接收端，返回值随接收端一起 drop
+    pub fn subscribe(&self) -> Subscription {
+        self.subscribed.store(true, Ordering::Relaxed);
+        self.listened.store(true, Ordering::Relaxed);
+        Subscription::new(&self.subscribers)
+    }
+
+    /// 对比设备列表、发出事件前加锁，避免热插拔线程和其他线程同时对比而重复发出事件
+    pub fn lock_changes(&self) -> MutexGuard<()> {
+        self.changing.lock().unwrap()
+    }
+
+    /// 注册事件回调，emit 时依次调用
+    pub fn add_callback(&self, callback: Arc<dyn Fn(CentralEvent) + Send + Sync>) {
+        self.callbacks.0.lock().unwrap().push(callback);
+    }
+
+    /// 调用过 subscribe 且返回的接收端都已经被 drop，并且没有注册回调和存活的等待者
+    ///
+    /// 从未调用过 subscribe 时不算空闲，事件照常缓存到队列中
+    pub fn is_idle(&self) -> bool {
+        self.subscribed.load(Ordering::Relaxed)
+            && self.subscribers.load(Ordering::Relaxed) == 0
+            && self.waiting.load(Ordering::Relaxed) == 0
+            && self.callbacks.0.lock().unwrap().is_empty()
+    }
+
+    /// 丢弃队列中已经没有接收端会读取的事件
+    pub fn clear_events(&self) {
+        while self.receiver.try_recv().is_ok() {}
+    }
+
     pub fn add_devices(&self,key:Uuid,device:HidDevice) ->Result<()>{
         // if self.devices.contains_key(&key) {
         //     bail!("Adding a device that's already in the map.");
@@ -43,10 +264,6 @@ impl Manager {
         Ok(())
     }
 
-    pub fn contains_device(&self,key:Uuid) -> bool {
-        self.devices.contains_key(&key)
-    }
-
     pub fn remove_device(&self,key:Uuid) -> Option<(Uuid, HidDevice)>{
         self.devices.remove(&key)
     }
@@ -58,13 +275,38 @@ impl Manager {
             .collect()
     }
 
-    pub fn device_keys(&self) -> Vec<Uuid> {
+    /// 逐个克隆设备交给 f，不一次性收集所有设备
+    ///
+    /// 先取出所有设备的 id，再逐个查找并克隆，调用 f 时不持有 DashMap 的锁，
+    /// f 中可以调用 manager 的其他方法。遍历期间被移除的设备会被跳过
+    pub fn for_each_device(&self, mut f: impl FnMut(&HidDevice)) {
+        let keys = self.devices.iter().map(|val| *val.key()).collect::<Vec<_>>();
+        for key in keys {
+            let device = match self.devices.get(&key) {
+                Some(val) => val.value().clone(),
+                None => continue,
+            };
+            f(&device);
+        }
+    }
+
+    /// 在遍历中过滤，只克隆满足条件的设备
+    pub fn devices_matching(&self, f: impl Fn(&HidDevice) -> bool) -> Vec<HidDevice> {
         self.devices
             .iter()
-            .map(|val| val.key().clone())
+            .filter(|val| f(val.value()))
+            .map(|val| val.value().clone())
             .collect()
     }
 
+    /// 返回第一个满足条件的设备，只克隆这一个
+    pub fn find_device(&self, f: impl Fn(&HidDevice) -> bool) -> Option<HidDevice> {
+        self.devices
+            .iter()
+            .find(|val| f(val.value()))
+            .map(|val| val.value().clone())
+    }
+
     pub fn _device_mut (
         &self,
         key:&Uuid,
@@ -75,4 +317,43 @@ impl Manager {
     pub fn device(&self, key:&Uuid) -> Option<HidDevice>{
         self.devices.get(key).map(|val| val.value().clone())
     }
+
+    pub fn device_by_path(&self, path:&OsStr) -> Option<HidDevice>{
+        self.devices
+            .iter()
+            .find(|val| val.value().path == path)
+            .map(|val| val.value().clone())
+    }
+}
+
+/// 按设备 id 合并事件，只保留每个设备从第一个事件之前到最后一个事件之后的净变化
+///
+/// 连接后又移除的设备不产生事件；移除后又重新连接的设备保留第一个 DeviceRemove 和最后一个 DeviceAdd；
+/// 其余设备保留最后一个事件。结果按每个设备第一次出现的顺序排列
+fn net_events(events: Vec<CentralEvent>) -> Vec<CentralEvent> {
+    // (设备 id, 第一个事件, 之后的最后一个事件)
+    let mut devices: Vec<(Uuid, CentralEvent, Option<CentralEvent>)> = vec![];
+    for event in events {
+        let id = match &event {
+            CentralEvent::DeviceAdd { id, .. } => *id,
+            CentralEvent::DeviceRemove { device, .. } => device.id,
+        };
+        match devices.iter_mut().find(|(v, ..)| *v == id) {
+            Some((_, _, last)) => *last = Some(event),
+            None => devices.push((id, event, None)),
+        }
+    }
+    let mut result = vec![];
+    for (_, first, last) in devices {
+        match (first, last) {
+            (CentralEvent::DeviceAdd { .. }, Some(CentralEvent::DeviceRemove { .. })) => {}
+            (first @ CentralEvent::DeviceRemove { .. }, Some(last @ CentralEvent::DeviceAdd { .. })) => {
+                result.push(first);
+                result.push(last);
+            }
+            (_, Some(last)) => result.push(last),
+            (first, None) => result.push(first),
+        }
+    }
+    result
 }
@@ -5,6 +5,8 @@
 use std::ffi::OsStr;
 use std::iter::once;
 use std::os::windows::ffi::OsStrExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use anyhow::{Result};
 use winapi::shared::minwindef::{LPARAM, LRESULT, UINT, WPARAM};
@@ -12,8 +14,9 @@ use winapi::shared::ntdef::LPCWSTR;
 use winapi::shared::windef::{HBRUSH, HCURSOR, HICON, HWND};
 use winapi::um::libloaderapi::GetModuleHandleW;
 use winapi::um::winuser::{
-    CreateWindowExW, DefWindowProcW, DispatchMessageW, GetMessageW, GetWindowLongPtrW, PostQuitMessage, RegisterClassW,
-    SetWindowLongPtrW, TranslateMessage, GWLP_USERDATA, MSG, WM_CREATE, WM_DESTROY, WM_DEVICECHANGE, WNDCLASSW,
+    CreateWindowExW, DefWindowProcW, DispatchMessageW, GetMessageW, GetWindowLongPtrW, MsgWaitForMultipleObjects, PeekMessageW,
+    PostQuitMessage, RegisterClassW, SetWindowLongPtrW, TranslateMessage, GWLP_USERDATA, MSG, PM_REMOVE, QS_ALLINPUT,
+    WM_CREATE, WM_DESTROY, WM_DEVICECHANGE, WM_QUIT, WNDCLASSW,
 };
 
 // use rusb::UsbContext;
@@ -35,6 +38,12 @@ pub struct PnPDetectWindows {
     // current_devices: HashSet<String>,
 }
 
+/// How long `detect_until` waits for messages before checking the stop flag again
+const STOP_POLL_MS: u32 = 50;
+
+/// RegisterClassW fails with this when another instance already registered the window class
+const ERROR_CLASS_ALREADY_EXISTS: i32 = 1410;
+
 impl PnPDetectWindows {
     pub fn new(callback: Box<dyn Fn()>) -> Self {
         let mut pnp_detect = Self {
@@ -74,12 +83,15 @@ impl PnPDetectWindows {
     // }
 
     /// Detect USB events: just run a Windows event loop
+    #[allow(dead_code)]
     pub fn detect(&self) -> Result<()> {
         unsafe {
+            self.attach();
             let mut msg: MSG = std::mem::MaybeUninit::zeroed().assume_init();
             loop {
                 let val = GetMessageW(&mut msg, self.hwnd, 0, 0);
-                if val == 0 {
+                // -1: GetMessageW failed, e.g. the window has been destroyed
+                if val == 0 || val == -1 {
                     break;
                 } else {
                     TranslateMessage(&msg);
@@ -90,6 +102,36 @@ impl PnPDetectWindows {
         Ok(())
     }
 
+    /// Like `detect`, but also returns once `stop` is set.
+    ///
+    /// Waits for messages in slices of `STOP_POLL_MS`, so the loop exits within that time
+    /// after the flag is set, without a WM_QUIT having to be posted from another thread.
+    pub fn detect_until(&self, stop: Arc<AtomicBool>) -> Result<()> {
+        unsafe {
+            self.attach();
+            let mut msg: MSG = std::mem::MaybeUninit::zeroed().assume_init();
+            while !stop.load(Ordering::Relaxed) {
+                MsgWaitForMultipleObjects(0, std::ptr::null(), 0, STOP_POLL_MS, QS_ALLINPUT);
+                while PeekMessageW(&mut msg, std::ptr::null_mut(), 0, 0, PM_REMOVE) != 0 {
+                    if msg.message == WM_QUIT {
+                        return Ok(());
+                    }
+                    TranslateMessage(&msg);
+                    DispatchMessageW(&msg);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Point the window's user data at `self`, which WM_DEVICECHANGE dispatches to.
+    ///
+    /// The pointer stored by WM_CREATE refers to the value before `new` returned it and is
+    /// stale once it has been moved; `self` stays put while the message loop borrows it.
+    unsafe fn attach(&self) {
+        SetWindowLongPtrW(self.hwnd, GWLP_USERDATA, self as *const Self as isize);
+    }
+
     /// Window procedure function to handle events
     pub unsafe extern "system" fn window_proc(hwnd: HWND, msg: UINT, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
         match msg {
@@ -106,8 +148,8 @@ impl PnPDetectWindows {
                 PostQuitMessage(0);
             }
             WM_DEVICECHANGE => {
-                let self_ptr = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut Self;
-                let window_state: &mut Self = match self_ptr.as_mut() {
+                let self_ptr = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *const Self;
+                let window_state: &Self = match self_ptr.as_ref() {
                     Some(v) => v,
                     None => return 0,
                 };
@@ -139,8 +181,12 @@ impl PnPDetectWindows {
             lpszClassName: winapi_class_name.as_ptr(),
         };
 
-        let error_code = unsafe { RegisterClassW(&wc) };
-        assert_ne!(error_code, 0, "failed to register the window class");
+        // Each instance (one per Adapter) creates its own window on its own thread,
+        // but the class is registered once per process
+        if unsafe { RegisterClassW(&wc) } == 0 {
+            let err = std::io::Error::last_os_error();
+            assert_eq!(err.raw_os_error(), Some(ERROR_CLASS_ALREADY_EXISTS), "failed to register the window class: {}", err);
+        }
 
         let window_name: Vec<u16> = OsStr::new("DisplaySwitchPnPDetectWindow")
             .encode_wide()
@@ -0,0 +1,51 @@
+/// 常见 HID usage page / usage 组合对应的名称，未知组合返回 None
+///
+/// 厂商自定义页（0xFF00 - 0xFFFF）下的任何 usage 都返回 "Vendor-defined"
+pub fn usage_label(usage_page: u16, usage: u16) -> Option<&'static str> {
+    let label = match (usage_page, usage) {
+        // Generic Desktop
+        (0x01, 0x01) => "Pointer",
+        (0x01, 0x02) => "Mouse",
+        (0x01, 0x04) => "Joystick",
+        (0x01, 0x05) => "Gamepad",
+        (0x01, 0x06) => "Keyboard",
+        (0x01, 0x07) => "Keypad",
+        (0x01, 0x08) => "Multi-axis Controller",
+        (0x01, 0x80) => "System Control",
+        // Telephony
+        (0x0B, 0x01) => "Phone",
+        (0x0B, 0x05) => "Headset",
+        // Consumer
+        (0x0C, 0x01) => "Consumer Control",
+        // Digitizers
+        (0x0D, 0x01) => "Digitizer",
+        (0x0D, 0x02) => "Pen",
+        (0x0D, 0x04) => "Touch Screen",
+        (0x0D, 0x05) => "Touch Pad",
+        // Sensors
+        (0x20, 0x01) => "Sensor",
+        // Power Device / Battery System
+        (0x84, 0x04) => "UPS",
+        (0x85, _) => "Battery System",
+        (0xFF00..=0xFFFF, _) => "Vendor-defined",
+        _ => return None,
+    };
+    Some(label)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usage_label_test() {
+        assert_eq!(usage_label(0x01, 0x06), Some("Keyboard"));
+        assert_eq!(usage_label(0x01, 0x02), Some("Mouse"));
+        assert_eq!(usage_label(0x01, 0x05), Some("Gamepad"));
+        assert_eq!(usage_label(0x0C, 0x01), Some("Consumer Control"));
+        assert_eq!(usage_label(0xFF00, 0x01), Some("Vendor-defined"));
+        assert_eq!(usage_label(0xFFA0, 0x20), Some("Vendor-defined"));
+        assert_eq!(usage_label(0x01, 0x03), None);
+        assert_eq!(usage_label(0x00, 0x00), None);
+    }
+}
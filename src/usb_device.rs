@@ -0,0 +1,272 @@
+use std::{ffi::OsString, mem::size_of, os::windows::prelude::OsStringExt};
+use uuid::Uuid;
+use windows::{
+    core::GUID,
+    Win32::{
+        Devices::{
+            DeviceAndDriverInstallation::{
+                CM_Get_Parent, CM_Get_Device_IDW, CM_Get_DevNode_PropertyW,
+                CM_Get_Device_Interface_List_SizeW, CM_Get_Device_Interface_ListW,
+                CM_GET_DEVICE_INTERFACE_LIST_PRESENT, CONFIGRET, CR_SUCCESS, CR_BUFFER_SMALL,
+            },
+            Properties::{DEVPKEY_Device_Address, DEVPKEY_Device_LocationInfo},
+        },
+        Foundation::{CloseHandle, HANDLE},
+        Storage::FileSystem::{CreateFileW, FILE_FLAGS_AND_ATTRIBUTES, FILE_GENERIC_WRITE, FILE_SHARE_WRITE, OPEN_EXISTING},
+        System::IO::DeviceIoControl,
+    },
+};
+
+use super::{
+    Error,
+    Result,
+    utils::to_uuid,
+    device_interface::DeviceInfoSet,
+    device_path::parse_device_path,
+};
+
+/// GUID_DEVINTERFACE_USB_DEVICE，所有 USB 设备都会注册该接口
+const GUID_DEVINTERFACE_USB_DEVICE: GUID = GUID::from_u128(0xa5dcbf10_6530_11d2_901f_00c04fb951ed);
+
+/// GUID_DEVINTERFACE_USB_HUB，hub（包括 root hub）注册的接口
+const GUID_DEVINTERFACE_USB_HUB: GUID = GUID::from_u128(0xf18a0e88_c30c_11d0_8815_00a0c906bed8);
+
+/// 向 hub 查询某个端口上设备的设备描述符和运行速度
+const IOCTL_USB_GET_NODE_CONNECTION_INFORMATION_EX: u32 = 0x00220448;
+
+/// Windows 8 起可用，查询设备是否运行在 SuperSpeed，EX 对 SuperSpeed 设备只报告 UsbHighSpeed
+const IOCTL_USB_GET_NODE_CONNECTION_INFORMATION_EX_V2: u32 = 0x0022045C;
+
+/// USB_NODE_CONNECTION_INFORMATION_EX（pack(1)）的长度：固定部分 35 字节，另留 32 个 USB_PIPE_INFO（各 11 字节）
+const CONNECTION_INFO_LEN: usize = 35 + 32 * 11;
+
+/// USB 设备当前的运行速度
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UsbSpeed {
+    /// 1.5 Mbps
+    Low,
+    /// 12 Mbps
+    Full,
+    /// 480 Mbps
+    High,
+    /// 5 Gbps 及以上
+    Super,
+}
+
+/// USB 设备信息，不区分设备类型（HID、WinUSB、存储设备等）
+#[derive(Debug, Clone)]
+pub struct UsbDeviceInfo {
+    pub path: OsString,
+    pub vendor_id: Option<u16>,     // 从路径解析，root hub 等设备没有
+    pub product_id: Option<u16>,
+    pub class: Uuid,                // 设备安装类（setup class）GUID
+}
+
+/// 获取所有 USB 设备，包括非 HID 设备
+///
+/// HID 设备的读写仍然使用 hid_device::all_hid_device
+pub fn all_usb_devices() -> Result<Vec<UsbDeviceInfo>> {
+    let device_info_set = DeviceInfoSet::new(None, true)?;
+    Ok(collect_devices(&device_info_set, GUID_DEVINTERFACE_USB_DEVICE))
+}
+
+/// 根据调用方提供的设备接口 GUID 枚举设备，如 WinUSB 设备注册的自定义接口 GUID
+pub fn all_devices_for_interface(guid: GUID) -> Result<Vec<UsbDeviceInfo>> {
+    let device_info_set = DeviceInfoSet::new(Some(&guid), true)?;
+    Ok(collect_devices(&device_info_set, guid))
+}
+
+fn collect_devices(device_info_set: &DeviceInfoSet, guid: GUID) -> Vec<UsbDeviceInfo> {
+    let mut list = vec![];
+    for (device_interface_name, device) in
+    device_info_set.iter_device_interfaces(guid){
+        let ids = parse_device_path(&device_interface_name);
+        list.push(UsbDeviceInfo {
+            vendor_id: ids.as_ref().map(|v| v.vendor_id),
+            product_id: ids.as_ref().map(|v| v.product_id),
+            class: to_uuid(&device.ClassGuid),
+            path: device_interface_name,
+        });
+    }
+    list
+}
+
+/// hid 设备所在 USB 设备的 (bcdUSB, 运行速度)，如 (0x0200, UsbSpeed::Full)
+///
+/// devinst 为 hid 设备的 devnode（SP_DEVINFO_DATA.DevInst）。沿父节点找到 USB 设备，
+/// 复合设备会跳过中间的 MI_xx 接口节点，再向设备所在的 hub 查询端口信息。
+/// 会打开 hub 并发送 IOCTL，比读取设备属性慢，只在需要时调用。
+/// 蓝牙等非 USB 设备返回 Error::NotFound
+pub(crate) fn usb_port_info(devinst: u32) -> Result<(u16, UsbSpeed)> {
+    let usb = usb_device_node(devinst)?;
+    let port = port_number(usb)?;
+    let hub = hub_path(parent(usb)?)?;
+    let handle = unsafe {
+        CreateFileW(hub.as_os_str(), FILE_GENERIC_WRITE, FILE_SHARE_WRITE, std::ptr::null(), OPEN_EXISTING, FILE_FLAGS_AND_ATTRIBUTES(0), HANDLE::default())
+    }?;
+    let result = connection_info(handle, port);
+    unsafe { CloseHandle(handle) };
+    result
+}
+
+/// hid 设备所在 USB 设备在 hub 上的端口号，只读取 devnode 属性，不打开 hub
+///
+/// 非 USB 设备返回 Error::NotFound
+pub(crate) fn usb_port_number(devinst: u32) -> Result<u32> {
+    port_number(usb_device_node(devinst)?)
+}
+
+/// hid 设备所在 USB 设备的位置信息（DEVPKEY_Device_LocationInfo），如 `Port_#0004.Hub_#0003`
+///
+/// 同一个 hub 端口上的设备得到相同的值，与序列号无关；非 USB 设备返回 Error::NotFound
+pub(crate) fn location_info(devinst: u32) -> Result<String> {
+    let usb = usb_device_node(devinst)?;
+    let mut size = 0u32;
+    let ret = unsafe { CM_Get_DevNode_PropertyW(usb, &DEVPKEY_Device_LocationInfo, &mut 0, std::ptr::null_mut(), &mut size, 0) };
+    if ret != CR_BUFFER_SMALL {
+        return Err(Error::NotFound);
+    }
+    let mut buffer = vec![0u16; (size as usize + 1) / 2];
+    cm_result(unsafe {
+        CM_Get_DevNode_PropertyW(usb, &DEVPKEY_Device_LocationInfo, &mut 0, buffer.as_mut_ptr() as _, &mut size, 0)
+    })?;
+    Ok(WideId(buffer).to_string_lossy())
+}
+
+/// 沿 hid 设备的父节点找到 USB 设备的 devnode，复合设备会跳过中间的 MI_xx 接口节点
+fn usb_device_node(devinst: u32) -> Result<u32> {
+    let mut usb = parent(devinst)?;
+    loop {
+        let id = instance_id(usb)?.to_string_lossy().to_uppercase();
+        if !id.starts_with("USB\\") {
+            return Err(Error::NotFound);
+        }
+        if !id.contains("&MI_") {
+            return Ok(usb);
+        }
+        usb = parent(usb)?;
+    }
+}
+
+/// 查询 hub 端口 port 上设备的 bcdUSB 和速度
+fn connection_info(hub: HANDLE, port: u32) -> Result<(u16, UsbSpeed)> {
+    let mut info = [0u8; CONNECTION_INFO_LEN];
+    info[..4].copy_from_slice(&port.to_le_bytes());
+    hub_io_control(hub, IOCTL_USB_GET_NODE_CONNECTION_INFORMATION_EX, &mut info)?;
+    // ConnectionIndex(4) 之后是 USB_DEVICE_DESCRIPTOR(18)，bcdUSB 位于描述符第 2 字节；
+    // 描述符之后是 CurrentConfigurationValue(1)、Speed(1)
+    let usb_version = u16::from_le_bytes([info[6], info[7]]);
+    let speed = match info[23] {
+        0 => UsbSpeed::Low,
+        1 => UsbSpeed::Full,
+        2 => UsbSpeed::High,
+        _ => UsbSpeed::Super,
+    };
+    if speed != UsbSpeed::High {
+        return Ok((usb_version, speed));
+    }
+    // USB_NODE_CONNECTION_INFORMATION_EX_V2：ConnectionIndex、Length、SupportedUsbProtocols（USB 1.1/2.0/3.0）、Flags，
+    // Flags 第 0 位为 DeviceIsOperatingAtSuperSpeedOrHigher
+    let mut info = [0u8; 16];
+    info[..4].copy_from_slice(&port.to_le_bytes());
+    info[4..8].copy_from_slice(&16u32.to_le_bytes());
+    info[8..12].copy_from_slice(&0b111u32.to_le_bytes());
+    match hub_io_control(hub, IOCTL_USB_GET_NODE_CONNECTION_INFORMATION_EX_V2, &mut info) {
+        Ok(()) if u32::from_le_bytes([info[12], info[13], info[14], info[15]]) & 1 != 0 => Ok((usb_version, UsbSpeed::Super)),
+        _ => Ok((usb_version, speed)),
+    }
+}
+
+/// 同步 DeviceIoControl，buffer 同时作为输入和输出
+fn hub_io_control(hub: HANDLE, code: u32, buffer: &mut [u8]) -> Result<()> {
+    let mut returned = 0u32;
+    let ok = unsafe {
+        DeviceIoControl(
+            hub,
+            code,
+            buffer.as_ptr() as _,
+            buffer.len() as u32,
+            buffer.as_mut_ptr() as _,
+            buffer.len() as u32,
+            &mut returned,
+            std::ptr::null_mut(),
+        )
+    };
+    if ok.as_bool() { Ok(()) } else { Err(Error::win32()) }
+}
+
+fn parent(devinst: u32) -> Result<u32> {
+    let mut parent = 0;
+    cm_result(unsafe { CM_Get_Parent(&mut parent, devinst, 0) })?;
+    Ok(parent)
+}
+
+/// devnode 的实例 id，以 0 结尾，可以直接传给 CM_Get_Device_Interface_ListW
+fn instance_id(devinst: u32) -> Result<WideId> {
+    // MAX_DEVICE_ID_LEN
+    let mut buffer = vec![0u16; 200];
+    cm_result(unsafe { CM_Get_Device_IDW(devinst, &mut buffer, 0) })?;
+    Ok(WideId(buffer))
+}
+
+/// USB 设备在 hub 上的端口号（DEVPKEY_Device_Address）
+fn port_number(devinst: u32) -> Result<u32> {
+    let mut port = 0u32;
+    let mut size = size_of::<u32>() as u32;
+    cm_result(unsafe {
+        CM_Get_DevNode_PropertyW(devinst, &DEVPKEY_Device_Address, &mut 0, &mut port as *mut u32 as _, &mut size, 0)
+    })?;
+    Ok(port)
+}
+
+/// hub devnode 的设备接口路径
+fn hub_path(hub: u32) -> Result<OsString> {
+    let id = instance_id(hub)?;
+    let mut len = 0u32;
+    cm_result(unsafe {
+        CM_Get_Device_Interface_List_SizeW(&mut len, &GUID_DEVINTERFACE_USB_HUB, id.0.as_ptr(), CM_GET_DEVICE_INTERFACE_LIST_PRESENT)
+    })?;
+    let mut buffer = vec![0u16; len as usize];
+    cm_result(unsafe {
+        CM_Get_Device_Interface_ListW(&GUID_DEVINTERFACE_USB_HUB, id.0.as_ptr(), &mut buffer, CM_GET_DEVICE_INTERFACE_LIST_PRESENT)
+    })?;
+    // 结果为以两个 0 结尾的路径列表，hub 只有一个接口
+    let end = buffer.iter().position(|v| *v == 0).unwrap_or(buffer.len());
+    if end == 0 {
+        return Err(Error::NotFound);
+    }
+    Ok(OsString::from_wide(&buffer[..end]))
+}
+
+fn cm_result(ret: CONFIGRET) -> Result<()> {
+    if ret == CR_SUCCESS { Ok(()) } else { Err(Error::NotFound) }
+}
+
+/// 以 0 结尾的 UTF-16 字符串，如实例 id
+struct WideId(Vec<u16>);
+
+impl WideId {
+    fn to_string_lossy(&self) -> String {
+        let end = self.0.iter().position(|v| *v == 0).unwrap_or(self.0.len());
+        String::from_utf16_lossy(&self.0[..end])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_usb_devices_test() {
+        let devices = all_usb_devices().unwrap();
+        println!("devices:{:?}", devices);
+        assert!(!devices.is_empty());
+    }
+
+    #[test]
+    fn all_devices_for_interface_test() {
+        let devices = all_devices_for_interface(GUID_DEVINTERFACE_USB_DEVICE).unwrap();
+        assert_eq!(devices.len(), all_usb_devices().unwrap().len());
+    }
+}
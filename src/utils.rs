@@ -1,7 +1,65 @@
+use std::ffi::OsStr;
 use uuid::Uuid;
 use ::windows::core::GUID;
 
 /// windows GUID to Uuid
 pub(crate) fn to_uuid(guid: &GUID) -> Uuid {
     Uuid::from_u128(guid.to_u128())
-}
\ No newline at end of file
+}
+
+/// 以 container id 为命名空间、小写的设备路径为名称生成 UUIDv5
+pub(crate) fn container_path_uuid(container_id: &GUID, path: &OsStr) -> Uuid {
+    Uuid::new_v5(&to_uuid(container_id), path.to_string_lossy().to_lowercase().as_bytes())
+}
+
+/// 小写设备路径的 64 位 FNV-1a 哈希
+///
+/// 不使用 DefaultHasher，它的算法在不同 Rust 版本间可能变化，这里的结果在任何版本、任何进程中都相同
+pub(crate) fn path_hash(path: &OsStr) -> u64 {
+    path.to_string_lossy().to_lowercase().bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// 以字符串形式序列化设备路径，便于 JSON 等格式阅读
+#[cfg(feature = "serde")]
+pub(crate) mod os_string_serde {
+    use std::ffi::OsString;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &OsString, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string_lossy())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<OsString, D::Error> {
+        String::deserialize(deserializer).map(OsString::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn container_path_uuid_test() {
+        let container = GUID::from_u128(0x12345678_1234_1234_1234_123456789abc);
+        let a = container_path_uuid(&container, OsStr::new(r"\\?\hid#vid_1234&pid_5678&mi_00#7&1&0&0000#{4d1e55b2-f16f-11cf-88cb-001111000030}"));
+        let b = container_path_uuid(&container, OsStr::new(r"\\?\hid#vid_1234&pid_5678&mi_01#7&2&0&0000#{4d1e55b2-f16f-11cf-88cb-001111000030}"));
+        assert_ne!(a, b);
+        assert_ne!(a, to_uuid(&container));
+        // 路径大小写不影响 id
+        let upper = container_path_uuid(&container, OsStr::new(r"\\?\HID#VID_1234&PID_5678&MI_00#7&1&0&0000#{4D1E55B2-F16F-11CF-88CB-001111000030}"));
+        assert_eq!(a, upper);
+    }
+
+    #[test]
+    fn path_hash_test() {
+        // FNV-1a 的标准测试向量
+        assert_eq!(path_hash(OsStr::new("")), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(path_hash(OsStr::new("a")), 0xaf63_dc4c_8601_ec8c);
+        let a = path_hash(OsStr::new(r"\\?\hid#vid_1234&pid_5678&mi_00#7&1&0&0000"));
+        let b = path_hash(OsStr::new(r"\\?\hid#vid_1234&pid_5678&mi_01#7&2&0&0000"));
+        assert_ne!(a, b);
+        assert_eq!(a, path_hash(OsStr::new(r"\\?\HID#VID_1234&PID_5678&MI_00#7&1&0&0000")));
+    }
+}
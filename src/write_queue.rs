@@ -0,0 +1,131 @@
+use std::{sync::{Arc, Mutex}, thread::{spawn, JoinHandle}, time::Duration};
+use crossbeam_channel::{bounded, Receiver, Sender};
+
+use super::{Error, Result, hid_device::HidDevice};
+
+/// HidDevice::writer 使用的队列长度
+pub const DEFAULT_CAPACITY: usize = 32;
+
+/// HidDevice::writer 使用的单个报告写入超时，设备停止响应时后台线程以 Error::Timeout 停止，drop 不会一直等待
+pub const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 顺序写入队列，由后台线程逐个调用 HidDevice::write 发送
+///
+/// 队列有容量上限，满时 enqueue 阻塞，直到后台线程发送出一个报告。
+/// 每个报告最多等待创建时指定的超时（见 HidDevice::writer_with_timeout），
+/// 某次写入失败后后台线程停止，错误由之后的 enqueue 和 finish 返回。
+/// drop 时等待队列中的报告发送完，需要得到发送结果时使用 finish
+///
+/// ```no_run
+/// let device = usb_manager::hid_device::all_hid_device().unwrap().remove(0);
+/// let writer = device.writer();
+/// writer.enqueue(0x00, &[0x01, 0x02]).unwrap();
+/// writer.finish().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct WriteQueue {
+    sender: Option<Sender<(u8, Vec<u8>)>>,
+    errors: Receiver<Error>,
+    worker: Option<JoinHandle<()>>,
+    failed: Mutex<Option<Arc<Error>>>, // 已经从 errors 取出的写入错误，留给 finish 返回
+}
+
+impl WriteQueue {
+    pub(crate) fn new(device: HidDevice, capacity: usize, timeout: Duration) -> Self {
+        let (sender, receiver) = bounded::<(u8, Vec<u8>)>(capacity);
+        let (error_sender, errors) = bounded(1);
+        let worker = spawn(move || {
+            for (report_id, data) in receiver {
+                if let Err(err) = device.write_timeout(report_id, &data, timeout) {
+                    let _ = error_sender.send(err);
+                    break;
+                }
+            }
+        });
+        Self {
+            sender: Some(sender),
+            errors,
+            worker: Some(worker),
+            failed: Mutex::new(None),
+        }
+    }
+
+    /// 将报告加入队列，队列满时阻塞
+    ///
+    /// 之前的写入失败时返回 Error::Other，其中是共享的原错误（`Arc<Error>`，可以 downcast_ref 取出），
+    /// 报告不会加入队列
+    pub fn enqueue(&self, report_id: u8, data: &[u8]) -> Result<()> {
+        if let Some(err) = self.failure() {
+            return Err(Error::Other(Box::new(err)));
+        }
+        let sender = self.sender.as_ref().ok_or(Error::NotOpen)?;
+        if sender.send((report_id, data.to_vec())).is_err() {
+            // 后台线程已经因为写入失败退出
+            return Err(match self.failure() {
+                Some(err) => Error::Other(Box::new(err)),
+                None => Error::Other("write queue stopped".into()),
+            });
+        }
+        Ok(())
+    }
+
+    /// 等待队列中的报告全部发送完并停止后台线程，返回写入错误，包括已经由 enqueue 返回过的
+    ///
+    /// enqueue 返回的错误都已经 drop 时返回原错误，否则同 enqueue 返回 Error::Other
+    pub fn finish(mut self) -> Result<()> {
+        self.stop();
+        self.failure();
+        match self.failed.get_mut().unwrap().take() {
+            Some(err) => Err(Arc::try_unwrap(err).unwrap_or_else(|err| Error::Other(Box::new(err)))),
+            None => Ok(()),
+        }
+    }
+
+    /// 后台线程的写入错误，第一次取出后保存在 failed 中
+    fn failure(&self) -> Option<Arc<Error>> {
+        let mut failed = self.failed.lock().unwrap();
+        if failed.is_none() {
+            *failed = self.errors.try_recv().ok().map(Arc::new);
+        }
+        failed.clone()
+    }
+
+    fn stop(&mut self) {
+        self.sender.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for WriteQueue {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_error_test() {
+        // output_report_byte_length 为 0，write 在打开设备之前返回 DataOverlength
+        let queue = HidDevice::default().writer();
+        queue.enqueue(0x00, &[1]).unwrap();
+        assert!(matches!(queue.finish(), Err(Error::DataOverlength)));
+    }
+
+    #[test]
+    fn enqueue_after_error_test() {
+        let queue = WriteQueue::new(HidDevice::default(), 1, DEFAULT_WRITE_TIMEOUT);
+        queue.enqueue(0x00, &[1]).unwrap();
+        let result = (0..10).map(|_| queue.enqueue(0x00, &[1])).find(|v| v.is_err());
+        let is_overlength = |err: &Error| matches!(err, Error::Other(err)
+            if matches!(err.downcast_ref::<Arc<Error>>().map(|v| v.as_ref()), Some(Error::DataOverlength)));
+        assert!(is_overlength(&result.unwrap().unwrap_err()));
+        assert!(is_overlength(&queue.enqueue(0x00, &[1]).unwrap_err()));
+        // enqueue 返回过的错误 finish 仍然返回，且是原错误
+        assert!(matches!(queue.finish(), Err(Error::DataOverlength)));
+    }
+}
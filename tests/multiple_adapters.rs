@@ -0,0 +1,54 @@
+#![cfg(windows)]
+//! 同一进程中同时运行多个 Adapter，各自的过滤条件、设备列表和事件互不影响
+
+use std::time::Duration;
+use usb_manager::{adapter::Adapter, filter::DeviceFilter, CentralEvent};
+
+fn keyboard_filter() -> DeviceFilter {
+    DeviceFilter {
+        usage_page: Some(0x01),
+        usage: Some(0x06),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn multiple_adapters_test() {
+    let keyboards = Adapter::with_filter(keyboard_filter());
+    let vendor = Adapter::new();
+    keyboards.start().unwrap();
+    // 第二个 Adapter 创建自己的监听窗口和线程
+    vendor.start().unwrap();
+    let _keyboard_events = keyboards.events().unwrap();
+    let vendor_events = vendor.events().unwrap();
+
+    assert!(keyboards.peripherals().unwrap().iter().all(|x| keyboard_filter().matches(x)));
+    assert!(vendor.peripherals().unwrap().iter().all(|x| DeviceFilter::vendor_defined().matches(x)));
+
+    // 停止一个 Adapter 不影响另一个继续监听
+    keyboards.stop();
+    for _ in 0..5 {
+        if let Ok(CentralEvent::DeviceAdd { id, .. }) = vendor_events.recv_timeout(Duration::from_millis(200)) {
+            assert!(DeviceFilter::vendor_defined().matches(&vendor.peripheral(&id).unwrap()));
+        }
+    }
+    vendor.stop();
+}
+
+#[test]
+fn restart_adapters_test() {
+    // 窗口类在第一个 Adapter 停止后仍然注册着，之后创建的 Adapter 直接使用
+    for _ in 0..2 {
+        let adapter = Adapter::with_filter(keyboard_filter());
+        adapter.start().unwrap();
+        adapter.stop();
+    }
+    let first = Adapter::new();
+    let second = Adapter::new();
+    first.start().unwrap();
+    second.start().unwrap();
+    assert_eq!(
+        first.peripherals().unwrap().len(),
+        second.peripherals().unwrap().len()
+    );
+}